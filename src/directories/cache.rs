@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use moka::sync::Cache;
+use parking_lot::RwLock;
+use tantivy::directory::error::{DeleteError, OpenReadError, OpenWriteError};
+use tantivy::directory::{FileHandle, OwnedBytes, WatchCallback, WatchHandle, WritePtr};
+use tantivy::{Directory, HasLen};
+
+type ReadCache = Cache<(PathBuf, u64, Range<usize>), OwnedBytes>;
+type Generations = Arc<RwLock<BTreeMap<PathBuf, u64>>>;
+
+/// A directory which caches the results of reads from an inner directory.
+///
+/// The cache is shared between every file handle and clone of the directory,
+/// so repeated reads of the same range are only served by the inner directory once.
+///
+/// Each file has a generation which is bumped whenever the file is changed,
+/// reads are cached under the generation the handle was opened with so a handle
+/// opened before the change can never serve its old bytes to newer handles.
+pub struct CachedDirectory<D: Directory> {
+    inner: D,
+    cache: ReadCache,
+    generations: Generations,
+}
+
+impl<D: Directory + Clone> CachedDirectory<D> {
+    /// Create a new cached directory holding up to `capacity` bytes of reads.
+    pub fn new(inner: D, capacity: u64) -> Self {
+        let cache = Cache::builder()
+            .max_capacity(capacity)
+            .weigher(|_, bytes: &OwnedBytes| bytes.len().try_into().unwrap_or(u32::MAX))
+            .support_invalidation_closures()
+            .build();
+
+        Self {
+            inner,
+            cache,
+            generations: Default::default(),
+        }
+    }
+
+    /// Bumps the generation of the given file and removes any cached reads of it.
+    fn invalidate(&self, path: &Path) {
+        *self
+            .generations
+            .write()
+            .entry(path.to_path_buf())
+            .or_default() += 1;
+
+        let path = path.to_path_buf();
+        // This can only fail if invalidation closures are not enabled.
+        let _ = self
+            .cache
+            .invalidate_entries_if(move |(file, _, _), _| *file == path);
+    }
+}
+
+impl<D: Directory> Debug for CachedDirectory<D> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CachedDirectory({:?})", self.inner)
+    }
+}
+
+impl<D: Directory + Clone> Clone for CachedDirectory<D> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            cache: self.cache.clone(),
+            generations: self.generations.clone(),
+        }
+    }
+}
+
+impl<D: Directory + Clone> Directory for CachedDirectory<D> {
+    fn get_file_handle(
+        &self,
+        path: &Path,
+    ) -> Result<Arc<dyn FileHandle>, OpenReadError> {
+        // The generation is read first, so if the file changes while the inner
+        // handle is being opened the handle's reads are never shared.
+        let generation = generation_of(&self.generations, path);
+        let inner = self.inner.get_file_handle(path)?;
+
+        Ok(Arc::new(CachedFileHandle {
+            path: path.to_path_buf(),
+            generation,
+            inner,
+            cache: self.cache.clone(),
+            generations: self.generations.clone(),
+        }))
+    }
+
+    fn delete(&self, path: &Path) -> Result<(), DeleteError> {
+        self.invalidate(path);
+        self.inner.delete(path)
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool, OpenReadError> {
+        self.inner.exists(path)
+    }
+
+    fn open_write(&self, path: &Path) -> Result<WritePtr, OpenWriteError> {
+        self.invalidate(path);
+        self.inner.open_write(path)
+    }
+
+    fn atomic_read(&self, path: &Path) -> Result<Vec<u8>, OpenReadError> {
+        self.inner.atomic_read(path)
+    }
+
+    fn atomic_write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        self.invalidate(path);
+        self.inner.atomic_write(path, data)
+    }
+
+    fn sync_directory(&self) -> io::Result<()> {
+        self.inner.sync_directory()
+    }
+
+    fn watch(&self, watch_callback: WatchCallback) -> tantivy::Result<WatchHandle> {
+        self.inner.watch(watch_callback)
+    }
+}
+
+fn generation_of(generations: &Generations, path: &Path) -> u64 {
+    generations.read().get(path).copied().unwrap_or_default()
+}
+
+/// A file handle which serves reads from the directory's shared cache.
+struct CachedFileHandle {
+    path: PathBuf,
+    generation: u64,
+    inner: Arc<dyn FileHandle>,
+    cache: ReadCache,
+    generations: Generations,
+}
+
+impl Debug for CachedFileHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CachedFileHandle({:?})", self.path)
+    }
+}
+
+impl HasLen for CachedFileHandle {
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+}
+
+impl FileHandle for CachedFileHandle {
+    fn read_bytes(&self, range: Range<usize>) -> io::Result<OwnedBytes> {
+        let key = (self.path.clone(), self.generation, range);
+        if let Some(bytes) = self.cache.get(&key) {
+            return Ok(bytes);
+        }
+
+        let bytes = self.inner.read_bytes(key.2.clone())?;
+        // Reads from a handle opened before the file changed would never be hit.
+        if generation_of(&self.generations, &self.path) == self.generation {
+            self.cache.insert(key, bytes.clone());
+        }
+
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tantivy::directory::RamDirectory;
+
+    use super::*;
+
+    /// A directory which counts the reads made through its file handles.
+    #[derive(Debug, Clone, Default)]
+    struct CountingDirectory {
+        inner: RamDirectory,
+        reads: Arc<AtomicUsize>,
+    }
+
+    #[derive(Debug)]
+    struct CountingFileHandle {
+        inner: Arc<dyn FileHandle>,
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl HasLen for CountingFileHandle {
+        fn len(&self) -> usize {
+            self.inner.len()
+        }
+    }
+
+    impl FileHandle for CountingFileHandle {
+        fn read_bytes(&self, range: Range<usize>) -> io::Result<OwnedBytes> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            self.inner.read_bytes(range)
+        }
+    }
+
+    impl Directory for CountingDirectory {
+        fn get_file_handle(
+            &self,
+            path: &Path,
+        ) -> Result<Arc<dyn FileHandle>, OpenReadError> {
+            Ok(Arc::new(CountingFileHandle {
+                inner: self.inner.get_file_handle(path)?,
+                reads: self.reads.clone(),
+            }))
+        }
+
+        fn delete(&self, path: &Path) -> Result<(), DeleteError> {
+            self.inner.delete(path)
+        }
+
+        fn exists(&self, path: &Path) -> Result<bool, OpenReadError> {
+            self.inner.exists(path)
+        }
+
+        fn open_write(&self, path: &Path) -> Result<WritePtr, OpenWriteError> {
+            self.inner.open_write(path)
+        }
+
+        fn atomic_read(&self, path: &Path) -> Result<Vec<u8>, OpenReadError> {
+            self.inner.atomic_read(path)
+        }
+
+        fn atomic_write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+            self.inner.atomic_write(path, data)
+        }
+
+        fn sync_directory(&self) -> io::Result<()> {
+            self.inner.sync_directory()
+        }
+
+        fn watch(&self, watch_callback: WatchCallback) -> tantivy::Result<WatchHandle> {
+            self.inner.watch(watch_callback)
+        }
+    }
+
+    #[test]
+    fn test_reads_are_shared_between_handles() {
+        let counting = CountingDirectory::default();
+        let cached = CachedDirectory::new(counting.clone(), 1 << 20);
+
+        let path = Path::new("hello.txt");
+        cached.atomic_write(path, b"hello world").unwrap();
+
+        let first = cached.get_file_handle(path).unwrap();
+        assert_eq!(first.read_bytes(0..5).unwrap().as_slice(), b"hello");
+        assert_eq!(counting.reads.load(Ordering::Relaxed), 1);
+
+        let second = cached.clone().get_file_handle(path).unwrap();
+        assert_eq!(second.read_bytes(0..5).unwrap().as_slice(), b"hello");
+        assert_eq!(counting.reads.load(Ordering::Relaxed), 1);
+
+        assert_eq!(second.read_bytes(6..11).unwrap().as_slice(), b"world");
+        assert_eq!(counting.reads.load(Ordering::Relaxed), 2);
+
+        cached.atomic_write(path, b"jello world").unwrap();
+        let third = cached.get_file_handle(path).unwrap();
+        assert_eq!(third.read_bytes(0..5).unwrap().as_slice(), b"jello");
+        assert_eq!(counting.reads.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_stale_handles_do_not_repopulate_cache() {
+        let cached = CachedDirectory::new(RamDirectory::create(), 1 << 20);
+
+        let path = Path::new("hello.txt");
+        cached.atomic_write(path, b"hello world").unwrap();
+
+        let stale = cached.get_file_handle(path).unwrap();
+        cached.atomic_write(path, b"jello world").unwrap();
+        assert_eq!(stale.read_bytes(0..5).unwrap().as_slice(), b"hello");
+
+        let fresh = cached.get_file_handle(path).unwrap();
+        assert_eq!(fresh.read_bytes(0..5).unwrap().as_slice(), b"jello");
+    }
+}
@@ -0,0 +1,60 @@
+use std::io;
+use std::io::ErrorKind;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+
+use crate::metadata::NONCE_SIZE;
+
+/// A 256 bit key used to encrypt and decrypt segment files.
+pub type EncryptionKey = [u8; 32];
+
+/// Encrypts the given file contents using a freshly generated nonce.
+///
+/// The file's path is authenticated alongside its contents, so the ciphertext
+/// cannot be swapped with another file's or have another file's range pointed at it.
+/// Returns the nonce which must be stored alongside the file in order to decrypt it.
+pub(crate) fn encrypt(
+    key: &EncryptionKey,
+    path: &str,
+    plaintext: &[u8],
+) -> io::Result<([u8; NONCE_SIZE], Vec<u8>)> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let payload = Payload {
+        msg: plaintext,
+        aad: path.as_bytes(),
+    };
+
+    let ciphertext = cipher
+        .encrypt(&nonce, payload)
+        .map_err(|_| io::Error::other("Failed to encrypt segment file"))?;
+
+    Ok((nonce.into(), ciphertext))
+}
+
+/// Decrypts the given file contents using the nonce it was encrypted with.
+///
+/// This fails if the key is not the one the file was encrypted with, if the
+/// contents have been modified or if they were encrypted for a different path.
+pub(crate) fn decrypt(
+    key: &EncryptionKey,
+    path: &str,
+    nonce: &[u8; NONCE_SIZE],
+    ciphertext: &[u8],
+) -> io::Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let payload = Payload {
+        msg: ciphertext,
+        aad: path.as_bytes(),
+    };
+
+    cipher
+        .decrypt(Nonce::from_slice(nonce), payload)
+        .map_err(|_| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                "Failed to decrypt segment file, the key may be incorrect",
+            )
+        })
+}
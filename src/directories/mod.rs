@@ -1,9 +1,30 @@
+#[cfg(feature = "cache")]
+mod cache;
+#[cfg(feature = "encryption")]
+mod encryption;
 mod merger;
 mod reader;
+mod verify;
 mod writer;
 
+use std::path::Path;
+
+#[cfg(feature = "cache")]
+pub use cache::CachedDirectory;
+#[cfg(feature = "encryption")]
+pub use encryption::EncryptionKey;
 pub use merger::DirectoryMerger;
 pub use reader::DirectoryReader;
+pub use verify::{verify_segment, verify_segment_bytes, SegmentProblem, SegmentReport};
+#[cfg(test)]
+pub(crate) use writer::tests::{exported_segment, populated_writer};
 pub use writer::DirectoryWriter;
 
+/// Files which are never exported into, or read from, a segment.
 static IGNORE_FILES: &[&str] = &[".tantivy-meta.lock", ".tantivy-writer.lock"];
+
+/// Returns if the given path is one of the [IGNORE_FILES].
+fn is_ignored(path: &Path) -> bool {
+    let fp = path.to_string_lossy();
+    IGNORE_FILES.contains(&fp.as_ref())
+}
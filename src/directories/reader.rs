@@ -1,13 +1,17 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Formatter};
-use std::io;
 use std::io::{ErrorKind, Write};
-use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::{fs, io};
 
+use parking_lot::RwLock;
 use tantivy::directory::error::{DeleteError, OpenReadError, OpenWriteError};
 use tantivy::directory::{
     AntiCallToken,
     FileHandle,
+    MmapDirectory,
     OwnedBytes,
     TerminatingWrite,
     WatchCallback,
@@ -17,8 +21,16 @@ use tantivy::directory::{
 };
 use tantivy::Directory;
 
-use crate::directories::IGNORE_FILES;
-use crate::metadata::SegmentMetadata;
+#[cfg(feature = "encryption")]
+use crate::directories::encryption::{self, EncryptionKey};
+use crate::directories::is_ignored;
+use crate::metadata::{
+    locate_metadata,
+    metadata_checksum,
+    write_metadata_offsets,
+    SegmentMetadata,
+    NONCE_SIZE,
+};
 
 /// An immutable segment reader which act as a tantivy directory.
 pub struct DirectoryReader {
@@ -26,6 +38,9 @@ pub struct DirectoryReader {
     metadata: Arc<SegmentMetadata>,
     bytes: OwnedBytes,
     watcher: Arc<WatchCallbackList>,
+    file_handles: Arc<RwLock<BTreeMap<PathBuf, Weak<dyn FileHandle>>>>,
+    #[cfg(feature = "encryption")]
+    encryption_key: Option<EncryptionKey>,
 }
 
 impl DirectoryReader {
@@ -39,9 +54,250 @@ impl DirectoryReader {
             file_path: fp.as_ref().to_path_buf(),
             metadata: Arc::new(metadata),
             watcher: Default::default(),
+            file_handles: Default::default(),
+            #[cfg(feature = "encryption")]
+            encryption_key: None,
             bytes,
         }
     }
+
+    #[cfg(feature = "encryption")]
+    /// Sets the key used to decrypt any encrypted files within the segment.
+    pub fn with_encryption_key(mut self, key: EncryptionKey) -> Self {
+        self.encryption_key = Some(key);
+        self
+    }
+
+    /// Create a new directory reader from the complete bytes of an exported segment.
+    ///
+    /// The metadata is located using the footer written at the end of the segment.
+    /// Legacy segments are also supported, see
+    /// [LEGACY_FOOTER_SIZE](crate::metadata::LEGACY_FOOTER_SIZE).
+    pub fn from_segment_bytes(
+        fp: impl AsRef<Path>,
+        bytes: OwnedBytes,
+    ) -> io::Result<Self> {
+        let footer = locate_metadata(&bytes)?;
+        let metadata = &bytes[footer.metadata_range()];
+        footer.verify_checksum(metadata)?;
+        let metadata = footer.decode_metadata(metadata)?;
+        Ok(Self::new(fp, bytes, metadata))
+    }
+
+    /// Opens the segment file at the given path using a read-only memory map.
+    ///
+    /// The file is mapped rather than copied into memory, so processes opening
+    /// the same segment share its physical pages through the page cache. This is
+    /// the recommended way of opening a segment from multiple processes.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        Self::from_segment_bytes(path, map_file(path)?)
+    }
+
+    /// Opens a segment embedded within a larger file at the given offset and length.
+    ///
+    /// The file is memory mapped as with [Self::open], so the segment is never
+    /// copied into memory, and all offsets within the segment are relative to
+    /// the start of its window.
+    pub fn open_at(path: impl AsRef<Path>, offset: u64, len: u64) -> io::Result<Self> {
+        let path = path.as_ref();
+        let bytes = map_file(path)?;
+
+        let window = offset
+            .checked_add(len)
+            .filter(|end| *end <= bytes.len() as u64)
+            .map(|end| offset as usize..end as usize)
+            .ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "Segment window is outside of the bounds of the file",
+                )
+            })?;
+
+        Self::from_segment_bytes(path, bytes.slice(window))
+    }
+
+    /// The names of the tantivy indexes contained within the segment.
+    pub fn indexes(&self) -> &[String] {
+        self.metadata.indexes()
+    }
+
+    /// The set of files contained within the segment.
+    pub fn files(&self) -> BTreeSet<PathBuf> {
+        self.metadata.files().keys().map(PathBuf::from).collect()
+    }
+
+    /// Writes each file within the segment out to the given directory.
+    ///
+    /// This recreates a normal tantivy directory which can be opened by
+    /// tooling expecting the individual files on disk.
+    ///
+    /// The file names come from the segment metadata, so any name which is not
+    /// a plain relative path is rejected before anything is written.
+    pub fn extract_to(&self, dir: &Path) -> io::Result<()> {
+        let files = self
+            .metadata
+            .files()
+            .iter()
+            .map(|(file, pos)| Ok((extract_path(dir, file)?, file, pos)))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        fs::create_dir_all(dir)?;
+        for (path, file, pos) in files {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            let bytes = self.read_file(file, pos)?;
+            fs::write(path, bytes.as_slice())?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a new segment containing only the given files to the writer.
+    ///
+    /// The files are copied as-is, so encrypted files remain encrypted with
+    /// their original nonces. Duplicate paths are only written once, and an
+    /// index is only kept if one of the given files is within it, see
+    /// [DirectoryWriter::add_index](crate::DirectoryWriter::add_index).
+    ///
+    /// An error is returned before anything is written if any of the files do
+    /// not exist within the segment.
+    pub fn project<W: Write>(&self, paths: &[&str], mut writer: W) -> io::Result<()> {
+        let mut files = Vec::with_capacity(paths.len());
+        for path in paths {
+            if files.iter().any(|(file, _)| file == path) {
+                continue;
+            }
+
+            let pos = self.metadata.get_location(path).ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("File {path:?} does not exist within the segment"),
+                )
+            })?;
+            files.push((*path, pos));
+        }
+
+        let mut cursor = 0;
+        let mut metadata = SegmentMetadata::default();
+        for name in self.metadata.indexes() {
+            let index = Path::new(name);
+            if files
+                .iter()
+                .any(|(file, _)| Path::new(file).starts_with(index))
+            {
+                metadata.add_index(name.clone());
+            }
+        }
+
+        for (path, pos) in files {
+            let bytes = self.slice_range(&pos)?;
+            writer.write_all(&bytes)?;
+
+            let file_start = cursor;
+            cursor += bytes.len() as u64;
+
+            metadata.add_file(path.to_string(), file_start..cursor);
+            if let Some(nonce) = self.metadata.get_nonce(path) {
+                metadata.add_nonce(path.to_string(), nonce);
+            }
+        }
+
+        let metadata_start = cursor;
+        let bytes = metadata.to_bytes()?;
+        writer.write_all(&bytes)?;
+
+        write_metadata_offsets(
+            &mut writer,
+            metadata_start,
+            bytes.len() as u64,
+            metadata_checksum(&bytes),
+        )?;
+        writer.flush()
+    }
+
+    /// Gets the bytes of a given file within the segment.
+    fn file_bytes(&self, path: &Path) -> Result<OwnedBytes, OpenReadError> {
+        if is_ignored(path) {
+            return Err(OpenReadError::wrap_io_error(
+                io::Error::new(
+                    ErrorKind::NotFound,
+                    "Lock files are never stored within an exported segment",
+                ),
+                path.to_path_buf(),
+            ));
+        }
+
+        let path_str = path.to_string_lossy();
+        let pos = self
+            .metadata
+            .get_location(&path_str)
+            .ok_or_else(|| OpenReadError::FileDoesNotExist(path.to_path_buf()))?;
+
+        self.read_file(&path_str, &pos)
+            .map_err(|e| OpenReadError::wrap_io_error(e, path.to_path_buf()))
+    }
+
+    /// Reads the contents of a file at the given location within the segment.
+    ///
+    /// If the file is encrypted it is decrypted before being returned.
+    fn read_file(&self, file: &str, pos: &Range<u64>) -> io::Result<OwnedBytes> {
+        let bytes = self.slice_range(pos)?;
+        match self.metadata.get_nonce(file) {
+            None => Ok(bytes),
+            Some(nonce) => self.decrypt_file(file, &nonce, bytes),
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    fn decrypt_file(
+        &self,
+        file: &str,
+        nonce: &[u8; NONCE_SIZE],
+        bytes: OwnedBytes,
+    ) -> io::Result<OwnedBytes> {
+        let key = self.encryption_key.as_ref().ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                "Segment file is encrypted but no encryption key was provided",
+            )
+        })?;
+
+        encryption::decrypt(key, file, nonce, &bytes).map(OwnedBytes::new)
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    fn decrypt_file(
+        &self,
+        _file: &str,
+        _nonce: &[u8; NONCE_SIZE],
+        _bytes: OwnedBytes,
+    ) -> io::Result<OwnedBytes> {
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "Segment file is encrypted but the `encryption` feature is not enabled",
+        ))
+    }
+
+    /// Slices the segment bytes, checking the range is within the segment.
+    ///
+    /// The ranges come from the segment metadata so a corrupt segment
+    /// could otherwise cause a panic.
+    fn slice_range(&self, pos: &Range<u64>) -> io::Result<OwnedBytes> {
+        if pos.start > pos.end || pos.end > self.bytes.len() as u64 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "File range {pos:?} is outside of the segment bounds (0..{})",
+                    self.bytes.len(),
+                ),
+            ));
+        }
+
+        Ok(self.bytes.slice(pos.start as usize..pos.end as usize))
+    }
 }
 
 impl Debug for DirectoryReader {
@@ -57,6 +313,9 @@ impl Clone for DirectoryReader {
             metadata: self.metadata.clone(),
             bytes: self.bytes.clone(),
             watcher: self.watcher.clone(),
+            file_handles: self.file_handles.clone(),
+            #[cfg(feature = "encryption")]
+            encryption_key: self.encryption_key,
         }
     }
 }
@@ -66,25 +325,27 @@ impl Directory for DirectoryReader {
         &self,
         path: &Path,
     ) -> Result<Arc<dyn FileHandle>, OpenReadError> {
-        let path_str = path.to_string_lossy();
-        let pos = self
-            .metadata
-            .get_location(&path_str)
-            .ok_or_else(|| OpenReadError::FileDoesNotExist(path.to_path_buf()))?;
+        // Handles are only held weakly, so decrypted files are not kept in
+        // memory once tantivy has dropped them.
+        if let Some(handle) = self.file_handles.read().get(path).and_then(Weak::upgrade)
+        {
+            return Ok(handle);
+        }
 
-        Ok(Arc::new(
-            self.bytes.slice(pos.start as usize..pos.end as usize),
-        ))
+        let handle: Arc<dyn FileHandle> = Arc::new(self.file_bytes(path)?);
+        self.file_handles
+            .write()
+            .insert(path.to_path_buf(), Arc::downgrade(&handle));
+
+        Ok(handle)
     }
 
     fn delete(&self, path: &Path) -> Result<(), DeleteError> {
-        let fp = path.to_string_lossy();
-        if IGNORE_FILES.contains(&fp.as_ref()) {
+        if is_ignored(path) {
             Ok(())
         } else {
             Err(DeleteError::IoError {
-                io_error: Arc::new(io::Error::new(
-                    ErrorKind::Other,
+                io_error: Arc::new(io::Error::other(
                     "Cannot perform mutable operations on a immutable segment",
                 )),
                 filepath: path.to_path_buf(),
@@ -93,10 +354,10 @@ impl Directory for DirectoryReader {
     }
 
     fn exists(&self, path: &Path) -> Result<bool, OpenReadError> {
-        let fp = path.to_string_lossy();
-        if !IGNORE_FILES.contains(&fp.as_ref()) {
+        if is_ignored(path) {
             Ok(false)
         } else {
+            let fp = path.to_string_lossy();
             Ok(self.metadata.get_location(&fp).is_some())
         }
     }
@@ -106,16 +367,7 @@ impl Directory for DirectoryReader {
     }
 
     fn atomic_read(&self, path: &Path) -> Result<Vec<u8>, OpenReadError> {
-        let path_str = path.to_string_lossy();
-        let pos = self
-            .metadata
-            .get_location(&path_str)
-            .ok_or_else(|| OpenReadError::FileDoesNotExist(path.to_path_buf()))?;
-
-        Ok(self
-            .bytes
-            .slice(pos.start as usize..pos.end as usize)
-            .to_vec())
+        Ok(self.file_bytes(path)?.to_vec())
     }
 
     fn atomic_write(&self, _path: &Path, _data: &[u8]) -> io::Result<()> {
@@ -131,6 +383,27 @@ impl Directory for DirectoryReader {
     }
 }
 
+/// Joins a file name from the segment metadata onto the extraction directory.
+///
+/// Only names made up of normal components are allowed, so the file can never
+/// be written outside of the directory.
+fn extract_path(dir: &Path, file: &str) -> io::Result<PathBuf> {
+    let path = Path::new(file);
+    let is_relative = path.components().next().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+
+    if !is_relative {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("File {file:?} is not a relative path within the segment"),
+        ));
+    }
+
+    Ok(dir.join(path))
+}
+
 /// A writer which only performs no ops while returning ok.
 pub struct NoOpWriter;
 
@@ -149,3 +422,428 @@ impl TerminatingWrite for NoOpWriter {
         Ok(())
     }
 }
+
+/// Memory maps the file at the given path.
+fn map_file(path: &Path) -> io::Result<OwnedBytes> {
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(ErrorKind::InvalidInput, "Segment path is not a file")
+    })?;
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+
+    let dir = MmapDirectory::open(parent)
+        .map_err(|e| io::Error::new(ErrorKind::NotFound, e.to_string()))?;
+    match dir.open_read(Path::new(file_name)) {
+        Ok(slice) => slice.read_bytes(),
+        Err(OpenReadError::IoError { io_error, .. }) => {
+            Err(io::Error::new(io_error.kind(), io_error.to_string()))
+        },
+        Err(e) => Err(io::Error::new(ErrorKind::NotFound, e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tantivy::directory::RamDirectory;
+    use tantivy::Index;
+
+    use super::*;
+    #[cfg(feature = "encryption")]
+    use crate::directories::populated_writer;
+    use crate::directories::{exported_segment, IGNORE_FILES};
+    use crate::DirectoryWriter;
+
+    fn exported_reader() -> DirectoryReader {
+        let (_, segment) = exported_segment();
+        DirectoryReader::from_segment_bytes("segment", OwnedBytes::new(segment)).unwrap()
+    }
+
+    #[test]
+    fn test_extract_to() {
+        let reader = exported_reader();
+
+        let out = tempfile::tempdir().unwrap();
+        reader.extract_to(out.path()).unwrap();
+
+        for (file, pos) in reader.metadata.files() {
+            let written = fs::read(out.path().join(file)).unwrap();
+            assert_eq!(written.len() as u64, pos.end - pos.start);
+        }
+
+        let index = Index::open(MmapDirectory::open(out.path()).unwrap()).unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        assert_eq!(searcher.num_docs(), 2);
+    }
+
+    #[test]
+    fn test_extract_nested_and_malicious_paths() {
+        let segment = |name: &str| {
+            let mut metadata = SegmentMetadata::default();
+            metadata.add_file(name.to_string(), 0..5);
+
+            let mut segment = b"hello".to_vec();
+            let bytes = metadata.to_bytes().unwrap();
+            segment.extend_from_slice(&bytes);
+            write_metadata_offsets(
+                &mut segment,
+                5,
+                bytes.len() as u64,
+                metadata_checksum(&bytes),
+            )
+            .unwrap();
+
+            DirectoryReader::from_segment_bytes("segment", OwnedBytes::new(segment))
+                .unwrap()
+        };
+
+        let out = tempfile::tempdir().unwrap();
+        let dir = out.path().join("extracted");
+        segment("nested/dir/file.txt").extract_to(&dir).unwrap();
+        assert_eq!(fs::read(dir.join("nested/dir/file.txt")).unwrap(), b"hello");
+
+        for name in [
+            "../escape.txt",
+            "nested/../../escape.txt",
+            "/absolute.txt",
+            "",
+        ] {
+            let err = segment(name).extract_to(&dir).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{name:?}");
+        }
+        assert!(!out.path().join("escape.txt").exists());
+    }
+
+    #[test]
+    fn test_empty_segment() {
+        let dir = MmapDirectory::create_from_tempdir().unwrap();
+        let writer = DirectoryWriter::new(dir);
+
+        let mut segment = Vec::new();
+        writer.write_segment(&mut segment).unwrap();
+
+        let reader =
+            DirectoryReader::from_segment_bytes("segment", OwnedBytes::new(segment))
+                .unwrap();
+        assert!(reader.files().is_empty());
+
+        let path = Path::new("meta.json");
+        assert!(!reader.exists(path).unwrap());
+        assert!(matches!(
+            reader.atomic_read(path),
+            Err(OpenReadError::FileDoesNotExist(_))
+        ));
+
+        let out = tempfile::tempdir().unwrap();
+        reader.extract_to(out.path()).unwrap();
+        assert_eq!(fs::read_dir(out.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_open_at() {
+        let out = tempfile::tempdir().unwrap();
+        let path = out.path().join("container");
+        let mut container = fs::File::create(&path).unwrap();
+
+        let mut windows = Vec::new();
+        let mut offset = 0;
+        for _ in 0..2 {
+            let (writer, segment) = exported_segment();
+            container.write_all(&segment).unwrap();
+
+            windows.push((writer, offset, segment.len() as u64));
+            offset += segment.len() as u64;
+        }
+        container.flush().unwrap();
+
+        for (writer, offset, len) in windows {
+            let reader = DirectoryReader::open_at(&path, offset, len).unwrap();
+            assert_eq!(reader.files(), writer.files());
+            for file in writer.files() {
+                let expected = writer.atomic_read(&file).unwrap();
+                assert_eq!(reader.atomic_read(&file).unwrap(), expected);
+            }
+        }
+
+        for (offset, len) in [(offset, 1), (1, offset), (u64::MAX, 2)] {
+            let err = DirectoryReader::open_at(&path, offset, len).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_open_shared() {
+        let (writer, segment) = exported_segment();
+
+        let out = tempfile::tempdir().unwrap();
+        let path = out.path().join("segment");
+        fs::write(&path, segment).unwrap();
+
+        let first = DirectoryReader::open(&path).unwrap();
+        let second = DirectoryReader::open(&path).unwrap();
+        for reader in [&first, &second] {
+            assert_eq!(reader.files(), writer.files());
+            for file in writer.files() {
+                let expected = writer.atomic_read(&file).unwrap();
+                assert_eq!(reader.atomic_read(&file).unwrap(), expected);
+            }
+
+            let index = Index::open(reader.clone()).unwrap();
+            assert_eq!(index.reader().unwrap().searcher().num_docs(), 2);
+        }
+
+        let err = DirectoryReader::open(out.path().join("missing")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_project() {
+        let reader = exported_reader();
+        let files = reader.files();
+        assert!(files.len() >= 4);
+
+        let paths = files
+            .iter()
+            .take(2)
+            .map(|file| file.to_str().unwrap())
+            .collect::<Vec<_>>();
+
+        let mut segment = Vec::new();
+        reader.project(&paths, &mut segment).unwrap();
+
+        let projected =
+            DirectoryReader::from_segment_bytes("projected", OwnedBytes::new(segment))
+                .unwrap();
+        assert_eq!(
+            projected.files(),
+            paths.iter().map(PathBuf::from).collect::<BTreeSet<_>>(),
+        );
+        for path in &paths {
+            let path = Path::new(path);
+            assert_eq!(
+                projected.atomic_read(path).unwrap(),
+                reader.atomic_read(path).unwrap(),
+            );
+        }
+
+        let mut output = Vec::new();
+        let err = reader
+            .project(&[paths[0], "missing.txt"], &mut output)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert!(output.is_empty());
+
+        let mut deduped = Vec::new();
+        reader.project(&[paths[0], paths[0]], &mut deduped).unwrap();
+        let deduped =
+            DirectoryReader::from_segment_bytes("deduped", OwnedBytes::new(deduped))
+                .unwrap();
+        let file = deduped.metadata.get_location(paths[0]).unwrap();
+        assert_eq!(
+            deduped.metadata.files_by_offset(),
+            [(paths[0], file.clone())]
+        );
+        assert_eq!(deduped.metadata.gaps(), []);
+    }
+
+    #[test]
+    fn test_project_indexes() {
+        let writer = DirectoryWriter::new(RamDirectory::create());
+        writer.atomic_write(Path::new("logs/a.txt"), b"a").unwrap();
+        writer
+            .atomic_write(Path::new("metrics/b.txt"), b"b")
+            .unwrap();
+        writer.add_index("logs");
+        writer.add_index("metrics");
+
+        let mut segment = Vec::new();
+        writer.write_segment(&mut segment).unwrap();
+        let reader =
+            DirectoryReader::from_segment_bytes("segment", OwnedBytes::new(segment))
+                .unwrap();
+
+        let mut projected = Vec::new();
+        reader.project(&["logs/a.txt"], &mut projected).unwrap();
+        let projected =
+            DirectoryReader::from_segment_bytes("projected", OwnedBytes::new(projected))
+                .unwrap();
+        assert_eq!(projected.indexes(), ["logs"]);
+        assert_eq!(
+            projected.atomic_read(Path::new("logs/a.txt")).unwrap(),
+            b"a"
+        );
+    }
+
+    #[test]
+    fn test_file_handles_are_cached() {
+        let reader = exported_reader();
+        let path = Path::new("meta.json");
+
+        let first = reader.get_file_handle(path).unwrap();
+        let second = reader.get_file_handle(path).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(
+            first.read_bytes(0..first.len()).unwrap().as_slice(),
+            reader.atomic_read(path).unwrap(),
+        );
+
+        let cloned = reader.clone();
+        let third = cloned.get_file_handle(path).unwrap();
+        assert!(Arc::ptr_eq(&first, &third));
+        assert_eq!(reader.file_handles.read().len(), 1);
+
+        drop((first, second, third));
+        let cached = reader.file_handles.read().get(path).cloned().unwrap();
+        assert!(cached.upgrade().is_none());
+        assert!(reader.get_file_handle(path).is_ok());
+    }
+
+    #[test]
+    fn test_out_of_range_file() {
+        let mut metadata = SegmentMetadata::default();
+        metadata.add_file("valid.txt".to_string(), 0..4);
+        metadata.add_file("past-end.txt".to_string(), 2..64);
+        metadata.add_file("inverted.txt".to_string(), Range { start: 4, end: 2 });
+
+        let bytes = OwnedBytes::new(b"hello world".to_vec());
+        let reader = DirectoryReader::new("segment", bytes, metadata);
+
+        let valid = Path::new("valid.txt");
+        assert_eq!(reader.atomic_read(valid).unwrap(), b"hell");
+
+        for file in ["past-end.txt", "inverted.txt"] {
+            let path = Path::new(file);
+            assert!(matches!(
+                reader.get_file_handle(path),
+                Err(OpenReadError::IoError { .. })
+            ));
+            assert!(matches!(
+                reader.atomic_read(path),
+                Err(OpenReadError::IoError { .. })
+            ));
+        }
+
+        let out = tempfile::tempdir().unwrap();
+        let err = reader.extract_to(out.path()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_segment() {
+        let writer = populated_writer();
+
+        let key = [7; 32];
+        let mut segment = Vec::new();
+        writer.write_encrypted_segment(&mut segment, &key).unwrap();
+        let bytes = OwnedBytes::new(segment);
+
+        let meta = Path::new("meta.json");
+        let plaintext = writer.atomic_read(meta).unwrap();
+        assert!(!bytes
+            .as_slice()
+            .windows(plaintext.len())
+            .any(|w| w == plaintext));
+
+        let reader = DirectoryReader::from_segment_bytes("segment", bytes.clone())
+            .unwrap()
+            .with_encryption_key(key);
+        assert_eq!(reader.atomic_read(meta).unwrap(), plaintext);
+
+        let index = Index::open(reader).unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        assert_eq!(searcher.num_docs(), 2);
+
+        let wrong_key = DirectoryReader::from_segment_bytes("segment", bytes.clone())
+            .unwrap()
+            .with_encryption_key([8; 32]);
+        assert!(matches!(
+            wrong_key.atomic_read(meta),
+            Err(OpenReadError::IoError { .. })
+        ));
+
+        let no_key = DirectoryReader::from_segment_bytes("segment", bytes).unwrap();
+        assert!(matches!(
+            no_key.get_file_handle(meta),
+            Err(OpenReadError::IoError { .. })
+        ));
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_files_are_bound_to_their_path() {
+        let writer = DirectoryWriter::new(RamDirectory::create());
+        writer.atomic_write(Path::new("a.txt"), b"aaaa").unwrap();
+        writer.atomic_write(Path::new("b.txt"), b"bbbb").unwrap();
+
+        let key = [7; 32];
+        let mut segment = Vec::new();
+        writer.write_encrypted_segment(&mut segment, &key).unwrap();
+        let reader = DirectoryReader::from_segment_bytes(
+            "segment",
+            OwnedBytes::new(segment.clone()),
+        )
+        .unwrap()
+        .with_encryption_key(key);
+
+        // Point each file at the other's ciphertext and nonce.
+        let mut swapped = SegmentMetadata::default();
+        for (file, other) in [("a.txt", "b.txt"), ("b.txt", "a.txt")] {
+            let pos = reader.metadata.get_location(other).unwrap();
+            swapped.add_file(file.to_string(), pos);
+            swapped
+                .add_nonce(file.to_string(), reader.metadata.get_nonce(other).unwrap());
+        }
+
+        let files_end = reader.metadata.files_by_offset().last().unwrap().1.end;
+        segment.truncate(files_end as usize);
+        let metadata = swapped.to_bytes().unwrap();
+        segment.extend_from_slice(&metadata);
+        write_metadata_offsets(
+            &mut segment,
+            files_end,
+            metadata.len() as u64,
+            metadata_checksum(&metadata),
+        )
+        .unwrap();
+
+        let tampered =
+            DirectoryReader::from_segment_bytes("tampered", OwnedBytes::new(segment))
+                .unwrap()
+                .with_encryption_key(key);
+        assert_eq!(reader.atomic_read(Path::new("a.txt")).unwrap(), b"aaaa");
+        for file in ["a.txt", "b.txt"] {
+            assert!(matches!(
+                tampered.atomic_read(Path::new(file)),
+                Err(OpenReadError::IoError { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_ignored_files() {
+        let reader = exported_reader();
+
+        assert!(reader.exists(Path::new("meta.json")).unwrap());
+        assert!(!reader.exists(Path::new("missing.json")).unwrap());
+
+        for file in IGNORE_FILES {
+            let path = Path::new(file);
+            assert!(!reader.exists(path).unwrap());
+            assert!(reader.delete(path).is_ok());
+
+            for result in [
+                reader.get_file_handle(path).map(|_| ()),
+                reader.atomic_read(path).map(|_| ()),
+            ] {
+                match result {
+                    Err(OpenReadError::IoError { io_error, .. }) => {
+                        assert_eq!(io_error.kind(), ErrorKind::NotFound)
+                    },
+                    other => panic!("Expected a lock file error, got: {other:?}"),
+                }
+            }
+        }
+    }
+}
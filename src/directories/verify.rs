@@ -0,0 +1,287 @@
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::metadata::{locate_metadata, metadata_checksum, read_footer, SegmentFooter};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A single problem found while verifying a segment.
+pub enum SegmentProblem {
+    /// The metadata offsets at the end of the segment are missing or invalid.
+    InvalidFooter(String),
+    /// The metadata does not match the checksum stored in the footer.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// The segment metadata could not be deserialized.
+    InvalidMetadata(String),
+    /// The file's range is inverted or extends into the segment metadata.
+    FileOutOfBounds { file: String, range: Range<u64> },
+    /// The ranges of two files overlap one another.
+    OverlappingFiles { first: String, second: String },
+}
+
+impl Display for SegmentProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidFooter(e) => write!(f, "Invalid segment footer: {e}"),
+            Self::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Segment metadata checksum {actual:#010x} does not match \
+                the expected checksum {expected:#010x}"
+            ),
+            Self::InvalidMetadata(e) => write!(f, "Invalid segment metadata: {e}"),
+            Self::FileOutOfBounds { file, range } => {
+                write!(f, "File {file:?} has an out of bounds range {range:?}")
+            },
+            Self::OverlappingFiles { first, second } => {
+                write!(f, "File {first:?} overlaps file {second:?}")
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+/// The result of verifying a segment.
+pub struct SegmentReport {
+    /// The number of files contained within the segment metadata.
+    pub num_files: usize,
+    /// The problems found with the segment, if any.
+    pub problems: Vec<SegmentProblem>,
+}
+
+impl SegmentReport {
+    /// Returns if no problems were found with the segment.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Checks the integrity of the segment file at the given path.
+///
+/// The footer and metadata are validated along with each file's range.
+/// Only the footer and metadata are read from the file, the bytes of the files
+/// within the segment are never loaded.
+/// An error is only returned if the file itself cannot be read.
+pub fn verify_segment(path: impl AsRef<Path>) -> std::io::Result<SegmentReport> {
+    let mut file = File::open(path)?;
+    let footer = match read_footer(&mut file) {
+        Ok(footer) => footer,
+        Err(e) if e.kind() == ErrorKind::InvalidData => return Ok(invalid_footer(e)),
+        Err(e) => return Err(e),
+    };
+
+    let mut metadata = vec![0; footer.metadata_range().len()];
+    file.seek(SeekFrom::Start(footer.metadata.start))?;
+    file.read_exact(&mut metadata)?;
+
+    Ok(verify_metadata(&footer, &metadata))
+}
+
+/// Checks the integrity of the complete bytes of an exported segment.
+pub fn verify_segment_bytes(segment: &[u8]) -> SegmentReport {
+    match locate_metadata(segment) {
+        Ok(footer) => verify_metadata(&footer, &segment[footer.metadata_range()]),
+        Err(e) => invalid_footer(e),
+    }
+}
+
+fn invalid_footer(e: std::io::Error) -> SegmentReport {
+    SegmentReport {
+        num_files: 0,
+        problems: vec![SegmentProblem::InvalidFooter(e.to_string())],
+    }
+}
+
+/// Checks the metadata located by the footer and the ranges of its files.
+fn verify_metadata(footer: &SegmentFooter, metadata: &[u8]) -> SegmentReport {
+    let mut report = SegmentReport::default();
+
+    let actual = metadata_checksum(metadata);
+    if let Some(expected) = footer.checksum.filter(|expected| *expected != actual) {
+        report
+            .problems
+            .push(SegmentProblem::ChecksumMismatch { expected, actual });
+    }
+
+    let metadata = match footer.decode_metadata(metadata) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            report
+                .problems
+                .push(SegmentProblem::InvalidMetadata(e.to_string()));
+            return report;
+        },
+    };
+
+    report.num_files = metadata.files().len();
+
+    let mut files = Vec::with_capacity(metadata.files().len());
+    for (file, range) in metadata.files() {
+        if range.start > range.end || range.end > footer.metadata.start {
+            report.problems.push(SegmentProblem::FileOutOfBounds {
+                file: file.clone(),
+                range: range.clone(),
+            });
+        } else {
+            files.push((file, range));
+        }
+    }
+
+    // Once sorted, a file can only overlap the one ending furthest along before it.
+    files.sort_by_key(|(_, range)| (range.start, range.end));
+    let mut furthest: Option<(&String, &Range<u64>)> = None;
+    for (file, range) in files {
+        if let Some((prev_file, prev_range)) = furthest {
+            if range.start < prev_range.end && !range.is_empty() {
+                report.problems.push(SegmentProblem::OverlappingFiles {
+                    first: prev_file.clone(),
+                    second: file.clone(),
+                });
+            }
+
+            if range.end <= prev_range.end {
+                continue;
+            }
+        }
+
+        furthest = Some((file, range));
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::directories::exported_segment;
+    use crate::metadata::{
+        write_metadata_offsets,
+        SegmentMetadata,
+        METADATA_HEADER_SIZE,
+    };
+
+    fn build_segment(data: &[u8], metadata: &SegmentMetadata) -> Vec<u8> {
+        let mut segment = data.to_vec();
+        let bytes = metadata.to_bytes().unwrap();
+        let start = segment.len() as u64;
+        segment.extend_from_slice(&bytes);
+        write_metadata_offsets(
+            &mut segment,
+            start,
+            bytes.len() as u64,
+            metadata_checksum(&bytes),
+        )
+        .unwrap();
+        segment
+    }
+
+    #[test]
+    fn test_verify_valid_segment() {
+        let (_, segment) = exported_segment();
+
+        let out = tempfile::tempdir().unwrap();
+        let path = out.path().join("segment");
+        fs::write(&path, segment).unwrap();
+
+        let report = verify_segment(&path).unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert!(report.num_files > 0);
+
+        assert!(verify_segment(out.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_verify_bad_footer() {
+        let report = verify_segment_bytes(b"short");
+        assert!(matches!(
+            report.problems.as_slice(),
+            [SegmentProblem::InvalidFooter(_)]
+        ));
+
+        let mut segment = build_segment(b"hello", &SegmentMetadata::default());
+        let len = segment.len();
+        segment[len - 1] = u8::MAX;
+        let report = verify_segment_bytes(&segment);
+        assert!(matches!(
+            report.problems.as_slice(),
+            [SegmentProblem::InvalidFooter(_)]
+        ));
+
+        let out = tempfile::tempdir().unwrap();
+        let path = out.path().join("segment");
+        for bytes in [&b"short"[..], &segment] {
+            fs::write(&path, bytes).unwrap();
+            assert_eq!(verify_segment(&path).unwrap(), verify_segment_bytes(bytes));
+        }
+    }
+
+    #[test]
+    fn test_verify_checksum_mismatch() {
+        let mut metadata = SegmentMetadata::default();
+        metadata.add_file("hello.txt".to_string(), 0..5);
+        let mut segment = build_segment(b"hello", &metadata);
+
+        // The checksum follows the metadata start and length in the footer.
+        let checksum_start = segment.len() - METADATA_HEADER_SIZE + 16;
+        segment[checksum_start] ^= u8::MAX;
+
+        let report = verify_segment_bytes(&segment);
+        assert_eq!(report.num_files, 1);
+        assert!(matches!(
+            report.problems.as_slice(),
+            [SegmentProblem::ChecksumMismatch { expected, actual }] if expected != actual
+        ));
+
+        let out = tempfile::tempdir().unwrap();
+        let path = out.path().join("segment");
+        fs::write(&path, &segment).unwrap();
+        assert_eq!(verify_segment(&path).unwrap(), report);
+    }
+
+    #[test]
+    fn test_verify_bad_metadata() {
+        let mut segment = b"hello".to_vec();
+        segment.extend_from_slice(&[u8::MAX; 8]);
+        write_metadata_offsets(&mut segment, 5, 8, metadata_checksum(&[u8::MAX; 8]))
+            .unwrap();
+
+        let report = verify_segment_bytes(&segment);
+        assert!(matches!(
+            report.problems.as_slice(),
+            [SegmentProblem::InvalidMetadata(_)]
+        ));
+    }
+
+    #[test]
+    fn test_verify_bad_ranges() {
+        let mut metadata = SegmentMetadata::default();
+        metadata.add_file("a.txt".to_string(), 0..6);
+        metadata.add_file("b.txt".to_string(), 4..8);
+        metadata.add_file("c.txt".to_string(), 8..11);
+        metadata.add_file("inverted.txt".to_string(), Range { start: 4, end: 2 });
+        metadata.add_file("past-end.txt".to_string(), 8..64);
+
+        let report = verify_segment_bytes(&build_segment(b"hello world", &metadata));
+        assert_eq!(report.num_files, 5);
+        assert_eq!(
+            report.problems,
+            vec![
+                SegmentProblem::FileOutOfBounds {
+                    file: "inverted.txt".to_string(),
+                    range: Range { start: 4, end: 2 },
+                },
+                SegmentProblem::FileOutOfBounds {
+                    file: "past-end.txt".to_string(),
+                    range: 8..64,
+                },
+                SegmentProblem::OverlappingFiles {
+                    first: "a.txt".to_string(),
+                    second: "b.txt".to_string(),
+                },
+            ],
+        );
+    }
+}
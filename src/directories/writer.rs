@@ -1,17 +1,19 @@
 use std::collections::BTreeSet;
 use std::fmt::{Debug, Formatter};
 use std::io;
-use std::io::Write;
+use std::io::{ErrorKind, Write};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use parking_lot::Mutex;
 use tantivy::directory::error::{DeleteError, OpenReadError, OpenWriteError};
-use tantivy::directory::{FileHandle, WatchCallback, WatchHandle, WritePtr};
+use tantivy::directory::{FileHandle, OwnedBytes, WatchCallback, WatchHandle, WritePtr};
 use tantivy::Directory;
 
-use crate::directories::IGNORE_FILES;
-use crate::metadata::SegmentMetadata;
+#[cfg(feature = "encryption")]
+use crate::directories::encryption::{self, EncryptionKey};
+use crate::directories::is_ignored;
+use crate::metadata::{SegmentMetadata, METADATA_HEADER_SIZE};
 
 /// A writer which wraps an inner directory.
 ///
@@ -20,14 +22,29 @@ use crate::metadata::SegmentMetadata;
 pub struct DirectoryWriter<D: Directory> {
     inner: D,
     files_to_read: Arc<Mutex<BTreeSet<PathBuf>>>,
+    indexes: Arc<Mutex<Vec<String>>>,
 }
 
 impl<D: Directory + Clone> DirectoryWriter<D> {
     /// Create a new directory writer.
+    #[allow(unused)]
     pub(crate) fn new(inner: D) -> Self {
         Self {
             inner,
             files_to_read: Default::default(),
+            indexes: Default::default(),
+        }
+    }
+
+    /// Records the name of an index which is contained within the segment.
+    ///
+    /// The names are stored in the segment metadata when it is written. The
+    /// index's files are expected to be within a directory of the same name.
+    pub fn add_index(&self, name: impl Into<String>) {
+        let name = name.into();
+        let mut indexes = self.indexes.lock();
+        if !indexes.contains(&name) {
+            indexes.push(name);
         }
     }
 
@@ -36,40 +53,115 @@ impl<D: Directory + Clone> DirectoryWriter<D> {
         self.files_to_read.lock().clone()
     }
 
+    /// Estimates the size of the segment produced by [Self::write_segment] in bytes.
+    ///
+    /// This sums the length of each file and the size of the metadata describing
+    /// them. Encrypted segments will be slightly larger due to the
+    /// authentication tags and nonces.
+    pub fn estimated_segment_size(&self) -> io::Result<u64> {
+        let mut cursor = 0;
+        let mut metadata = self.new_metadata();
+
+        for file in self.files() {
+            let file_start = cursor;
+            cursor += self.open_file(&file)?.len() as u64;
+
+            metadata.add_file(file.to_string_lossy().to_string(), file_start..cursor);
+        }
+
+        let metadata_len = metadata.to_bytes()?.len() + METADATA_HEADER_SIZE;
+        Ok(cursor + metadata_len as u64)
+    }
+
     /// Writes the contents of the directory to a given writer.
-    pub fn write_segment<W: Write>(&self, mut writer: W) -> io::Result<()> {
+    pub fn write_segment<W: Write>(&self, writer: W) -> io::Result<()> {
+        self.write_segment_inner(writer, |_, bytes, _| Ok(bytes))
+    }
+
+    #[cfg(feature = "encryption")]
+    /// Writes the contents of the directory to a given writer, encrypting
+    /// each file with the given key.
+    ///
+    /// Each file is encrypted with its own nonce which is stored in the
+    /// segment metadata, the metadata itself remains in plaintext. The file's
+    /// path is authenticated with its contents, so files cannot be swapped.
+    pub fn write_encrypted_segment<W: Write>(
+        &self,
+        writer: W,
+        key: &EncryptionKey,
+    ) -> io::Result<()> {
+        self.write_segment_inner(writer, |fp, bytes, metadata| {
+            let (nonce, ciphertext) = encryption::encrypt(key, fp, &bytes)?;
+            metadata.add_nonce(fp.to_string(), nonce);
+            Ok(OwnedBytes::new(ciphertext))
+        })
+    }
+
+    /// Writes the contents of the directory to a given writer, passing each
+    /// file's contents through `prepare_file` before it's written.
+    fn write_segment_inner<W: Write>(
+        &self,
+        mut writer: W,
+        mut prepare_file: impl FnMut(
+            &str,
+            OwnedBytes,
+            &mut SegmentMetadata,
+        ) -> io::Result<OwnedBytes>,
+    ) -> io::Result<()> {
         let mut cursor = 0;
-        let mut metadata = SegmentMetadata::default();
+        let mut metadata = self.new_metadata();
 
         for file in self.files() {
-            let handle = match self.get_file_handle(&file) {
-                Ok(handle) => handle,
-                Err(OpenReadError::IoError { io_error, .. }) => {
-                    return Err(io::Error::new(io_error.kind(), io_error.to_string()))
-                },
-                _ => unimplemented!(),
-            };
+            let handle = self.open_file(&file)?;
+            let fp = file.to_string_lossy().to_string();
+            let bytes = handle.read_bytes(0..handle.len())?;
+            let bytes = prepare_file(&fp, bytes, &mut metadata)?;
 
             let file_start = cursor;
-            let bytes = handle.read_bytes(0..handle.len())?;
             writer.write_all(&bytes)?;
             cursor += bytes.len() as u64;
 
-            let fp = file.to_string_lossy().to_string();
             metadata.add_file(fp, file_start..cursor);
         }
 
         let metadata_start = cursor;
         let bytes = metadata.to_bytes()?;
         writer.write_all(&bytes)?;
-        cursor += bytes.len() as u64;
 
-        crate::metadata::write_metadata_offsets(&mut writer, metadata_start, cursor)?;
+        crate::metadata::write_metadata_offsets(
+            &mut writer,
+            metadata_start,
+            bytes.len() as u64,
+            crate::metadata::metadata_checksum(&bytes),
+        )?;
 
         writer.flush()?;
 
         Ok(())
     }
+
+    /// Creates the metadata for a new segment containing the recorded indexes.
+    fn new_metadata(&self) -> SegmentMetadata {
+        let mut metadata = SegmentMetadata::default();
+        for name in self.indexes.lock().iter() {
+            metadata.add_index(name.clone());
+        }
+        metadata
+    }
+
+    fn open_file(&self, file: &Path) -> io::Result<Arc<dyn FileHandle>> {
+        self.get_file_handle(file).map_err(|e| match e {
+            OpenReadError::IoError { io_error, .. } => {
+                io::Error::new(io_error.kind(), io_error.to_string())
+            },
+            OpenReadError::FileDoesNotExist(_) => {
+                io::Error::new(ErrorKind::NotFound, e.to_string())
+            },
+            OpenReadError::IncompatibleIndex(_) => {
+                io::Error::new(ErrorKind::InvalidData, e.to_string())
+            },
+        })
+    }
 }
 
 impl<D: Directory> Debug for DirectoryWriter<D> {
@@ -83,6 +175,7 @@ impl<D: Directory + Clone> Clone for DirectoryWriter<D> {
         Self {
             inner: self.inner.clone(),
             files_to_read: self.files_to_read.clone(),
+            indexes: self.indexes.clone(),
         }
     }
 }
@@ -105,8 +198,7 @@ impl<D: Directory + Clone> Directory for DirectoryWriter<D> {
     }
 
     fn open_write(&self, path: &Path) -> Result<WritePtr, OpenWriteError> {
-        let fp = path.to_string_lossy();
-        if !IGNORE_FILES.contains(&fp.as_ref()) {
+        if !is_ignored(path) {
             self.files_to_read.lock().insert(path.to_path_buf());
         }
         self.inner.open_write(path)
@@ -117,8 +209,7 @@ impl<D: Directory + Clone> Directory for DirectoryWriter<D> {
     }
 
     fn atomic_write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
-        let fp = path.to_string_lossy();
-        if !IGNORE_FILES.contains(&fp.as_ref()) {
+        if !is_ignored(path) {
             self.files_to_read.lock().insert(path.to_path_buf());
         }
         self.inner.atomic_write(path, data)
@@ -134,28 +225,93 @@ impl<D: Directory + Clone> Directory for DirectoryWriter<D> {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use tantivy::collector::TopDocs;
-    use tantivy::directory::MmapDirectory;
+    use tantivy::directory::{MmapDirectory, OwnedBytes};
     use tantivy::query::QueryParser;
     use tantivy::schema::*;
     use tantivy::{doc, Index, IndexSettings, ReloadPolicy};
 
     use super::*;
+    use crate::DirectoryReader;
 
     #[test]
     fn test_create_segment() {
+        let (_, segment) = exported_segment();
+        assert_eq!(segment.len(), 4325)
+    }
+
+    #[test]
+    fn test_estimated_segment_size() {
         let dir = MmapDirectory::create_from_tempdir().unwrap();
         let write = DirectoryWriter::new(dir);
+        assert_eq!(
+            write.estimated_segment_size().unwrap(),
+            (SegmentMetadata::default().to_bytes().unwrap().len() + METADATA_HEADER_SIZE)
+                as u64,
+        );
 
         create_segment(write.clone()).unwrap();
 
         let mut segment = Vec::new();
         write.write_segment(&mut segment).unwrap();
-        assert_eq!(segment.len(), 5995)
+        assert_eq!(
+            write.estimated_segment_size().unwrap(),
+            segment.len() as u64
+        );
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let writer = DirectoryWriter::new(MmapDirectory::open(dir.path()).unwrap());
+        writer
+            .atomic_write(Path::new("hello.txt"), b"hello")
+            .unwrap();
+        std::fs::remove_file(dir.path().join("hello.txt")).unwrap();
+
+        let err = writer.estimated_segment_size().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        let err = writer.write_segment(Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_write_segment_to_buffer_sink() {
+        let write = populated_writer();
+
+        let mut sink = io::Cursor::new(Vec::new());
+        write.write_segment(&mut sink).unwrap();
+
+        let bytes = OwnedBytes::new(sink.into_inner());
+        let reader = DirectoryReader::from_segment_bytes("segment", bytes).unwrap();
+        for file in write.files() {
+            let expected = write.atomic_read(&file).unwrap();
+            assert_eq!(reader.atomic_read(&file).unwrap(), expected);
+        }
+
+        let index = Index::open(reader).unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        assert_eq!(searcher.num_docs(), 2);
+    }
+
+    /// Creates a writer over a temporary directory containing a small index.
+    pub(crate) fn populated_writer() -> DirectoryWriter<MmapDirectory> {
+        let writer = DirectoryWriter::new(MmapDirectory::create_from_tempdir().unwrap());
+        create_segment(writer.clone()).unwrap();
+        writer
+    }
+
+    /// Creates a populated writer along with the segment it exports.
+    pub(crate) fn exported_segment() -> (DirectoryWriter<MmapDirectory>, Vec<u8>) {
+        let writer = populated_writer();
+
+        let mut segment = Vec::new();
+        writer.write_segment(&mut segment).unwrap();
+        (writer, segment)
     }
 
-    fn create_segment(directory: impl Directory) -> tantivy::Result<()> {
+    pub(crate) fn create_segment(directory: impl Directory) -> tantivy::Result<()> {
         let mut schema_builder = Schema::builder();
         schema_builder.add_text_field("title", TEXT | STORED);
         schema_builder.add_text_field("body", TEXT);
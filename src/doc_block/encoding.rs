@@ -1,13 +1,16 @@
 use std::borrow::Cow;
 use std::collections::BTreeMap;
-use std::hash::Hasher;
+use std::io;
 use std::mem::size_of;
 
 use bytecheck::CheckBytes;
 use rkyv::{Archive, Deserialize, Serialize};
+use serde_json::Value;
 use tantivy::HasLen;
 
+use crate::doc_block::{DocDigest, DocHasher, HashAlgorithm};
 use crate::document::{DocField, DocValue};
+use crate::schema::BasicSchema;
 
 #[repr(u8)]
 #[derive(
@@ -30,17 +33,186 @@ pub enum ValueType {
     Json = 5,
     /// The field is null.
     Null = 6,
+    /// The field value is of type `bool`.
+    Bool = 7,
 }
 
 /// The ID of the field in the doc.
 pub type FieldId = u16;
+
+#[repr(u8)]
+#[derive(
+    Debug, Default, Copy, Clone, Eq, PartialEq, Archive, Serialize, Deserialize,
+)]
+#[archive_attr(derive(CheckBytes))]
+/// The number of bytes used to encode each field ID.
+pub enum FieldIdWidth {
+    /// Field IDs are encoded as a `u8`.
+    U8 = 0,
+    #[default]
+    /// Field IDs are encoded as a `u16`.
+    U16 = 1,
+}
+
+impl FieldIdWidth {
+    /// Selects the field ID width for a schema with the given number of fields.
+    ///
+    /// Schemas with fewer than 256 fields use a `u8`, otherwise a `u16` is used.
+    pub fn for_num_fields(num_fields: usize) -> Self {
+        if num_fields < 256 {
+            Self::U8
+        } else {
+            Self::U16
+        }
+    }
+
+    #[inline]
+    /// The number of bytes an encoded field ID occupies.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::U8 => size_of::<u8>(),
+            Self::U16 => size_of::<u16>(),
+        }
+    }
+
+    #[inline]
+    /// The largest field ID which can be encoded with this width.
+    pub fn max_field_id(&self) -> FieldId {
+        match self {
+            Self::U8 => u8::MAX as FieldId,
+            Self::U16 => FieldId::MAX,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn write_to(
+        &self,
+        buffer: &mut Vec<u8>,
+        field_id: FieldId,
+        order: ByteOrder,
+    ) {
+        match self {
+            Self::U8 => {
+                let field_id = u8::try_from(field_id)
+                    .expect("Field ID should be checked against the width");
+                buffer.push(field_id);
+            },
+            Self::U16 => order.write_u16(buffer, field_id),
+        }
+    }
+
+    #[inline]
+    /// Feeds the field ID to the hasher exactly as it is encoded.
+    fn hash_into<H: DocHasher>(
+        &self,
+        hasher: &mut H,
+        field_id: FieldId,
+        order: ByteOrder,
+    ) {
+        match self {
+            Self::U8 => {
+                let field_id = u8::try_from(field_id)
+                    .expect("Field ID should be checked against the width");
+                hasher.update(&[field_id]);
+            },
+            Self::U16 => hasher.update(&order.encode_u16(field_id)),
+        }
+    }
+
+    #[inline]
+    /// Reads a field ID from the start of the buffer.
+    ///
+    /// Returns `None` if the buffer is too small to contain one.
+    pub(crate) fn read_from(
+        &self,
+        buffer: &mut &[u8],
+        order: ByteOrder,
+    ) -> Option<FieldId> {
+        match self {
+            Self::U8 => {
+                let (field_id, rest) = buffer.split_first()?;
+                *buffer = rest;
+                Some(*field_id as FieldId)
+            },
+            Self::U16 => order.read_u16(buffer),
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(
+    Debug, Default, Copy, Clone, Eq, PartialEq, Archive, Serialize, Deserialize,
+)]
+#[archive_attr(derive(CheckBytes))]
+/// The byte order used to encode the integers and floats within a document.
+///
+/// This must match between the writer and reader, so it is recorded in the schema.
+pub enum ByteOrder {
+    #[default]
+    /// Values are encoded in little-endian byte order.
+    LittleEndian = 0,
+    /// Values are encoded in big-endian byte order.
+    BigEndian = 1,
+}
+
+macro_rules! byte_order_int {
+    ($write:ident, $read:ident, $decode:ident, $encode:ident, $int:ty) => {
+        #[inline]
+        pub(crate) fn $write(&self, buffer: &mut Vec<u8>, value: $int) {
+            buffer.extend_from_slice(&self.$encode(value));
+        }
+
+        #[inline]
+        fn $encode(&self, value: $int) -> [u8; size_of::<$int>()] {
+            match self {
+                Self::LittleEndian => value.to_le_bytes(),
+                Self::BigEndian => value.to_be_bytes(),
+            }
+        }
+
+        #[inline]
+        /// Reads a value from the start of the buffer, returning `None` if
+        /// the buffer is too small.
+        pub(crate) fn $read(&self, buffer: &mut &[u8]) -> Option<$int> {
+            let int_bytes = buffer.get(..size_of::<$int>())?;
+            *buffer = &buffer[size_of::<$int>()..];
+
+            self.$decode(int_bytes)
+        }
+
+        #[inline]
+        fn $decode(&self, bytes: &[u8]) -> Option<$int> {
+            let slice = bytes.try_into().ok()?;
+            match self {
+                Self::LittleEndian => Some(<$int>::from_le_bytes(slice)),
+                Self::BigEndian => Some(<$int>::from_be_bytes(slice)),
+            }
+        }
+    };
+}
+
+impl ByteOrder {
+    /// The native byte order of the current platform.
+    pub const fn native() -> Self {
+        if cfg!(target_endian = "big") {
+            Self::BigEndian
+        } else {
+            Self::LittleEndian
+        }
+    }
+
+    byte_order_int!(write_u16, read_u16, decode_u16, encode_u16, u16);
+    byte_order_int!(write_u32, read_u32, decode_u32, encode_u32, u32);
+    byte_order_int!(write_u64, read_u64, decode_u64, encode_u64, u64);
+}
+
 /// The length of the field value in bytes.
 type FieldLen = u32;
 
 /// The size of the per-document header.
-const DOC_HEADER_SIZE: usize = 20;
+const DOC_HEADER_SIZE: usize = 24;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 /// The metadata information about the doc structure.
 pub struct DocHeader {
     /// The timestamp the document was created.
@@ -57,6 +229,10 @@ pub struct DocHeader {
     pub num_bytes: u16,
     /// The number of `json` fields in the doc.
     pub num_json: u16,
+    /// The number of `null` fields in the doc.
+    pub num_null: u16,
+    /// The number of `bool` fields in the doc.
+    pub num_bool: u16,
 }
 
 impl DocHeader {
@@ -70,35 +246,56 @@ impl DocHeader {
             num_f64: 0,
             num_bytes: 0,
             num_json: 0,
+            num_null: 0,
+            num_bool: 0,
         }
     }
 
     /// Writes the current doc header metadata into a given buffer.
     pub fn write_to(&self, writer: &mut Vec<u8>) {
+        self.write_to_with_order(writer, ByteOrder::default())
+    }
+
+    /// Writes the current doc header metadata into a given buffer using the
+    /// given byte order.
+    pub fn write_to_with_order(&self, writer: &mut Vec<u8>, order: ByteOrder) {
         writer.reserve(DOC_HEADER_SIZE);
-        writer.extend_from_slice(&self.timestamp.to_le_bytes());
-        writer.extend_from_slice(&self.num_string.to_le_bytes());
-        writer.extend_from_slice(&self.num_u64.to_le_bytes());
-        writer.extend_from_slice(&self.num_i64.to_le_bytes());
-        writer.extend_from_slice(&self.num_f64.to_le_bytes());
-        writer.extend_from_slice(&self.num_bytes.to_le_bytes());
-        writer.extend_from_slice(&self.num_json.to_le_bytes());
+        order.write_u64(writer, self.timestamp);
+        order.write_u16(writer, self.num_string);
+        order.write_u16(writer, self.num_u64);
+        order.write_u16(writer, self.num_i64);
+        order.write_u16(writer, self.num_f64);
+        order.write_u16(writer, self.num_bytes);
+        order.write_u16(writer, self.num_json);
+        order.write_u16(writer, self.num_null);
+        order.write_u16(writer, self.num_bool);
     }
 
     /// Attempts to read the header from the start of the reader.
-    pub fn try_read_from(mut reader: &[u8]) -> Option<Self> {
+    pub fn try_read_from(reader: &[u8]) -> Option<Self> {
+        Self::try_read_from_with_order(reader, ByteOrder::default())
+    }
+
+    /// Attempts to read the header from the start of the reader where the
+    /// document was encoded with the given byte order.
+    pub fn try_read_from_with_order(
+        mut reader: &[u8],
+        order: ByteOrder,
+    ) -> Option<Self> {
         if reader.len() < DOC_HEADER_SIZE {
             return None;
         }
 
         Some(Self {
-            timestamp: read_timestamp(&mut reader)?,
-            num_string: read_u16_le(&mut reader)?,
-            num_u64: read_u16_le(&mut reader)?,
-            num_i64: read_u16_le(&mut reader)?,
-            num_f64: read_u16_le(&mut reader)?,
-            num_bytes: read_u16_le(&mut reader)?,
-            num_json: read_u16_le(&mut reader)?,
+            timestamp: order.read_u64(&mut reader)?,
+            num_string: order.read_u16(&mut reader)?,
+            num_u64: order.read_u16(&mut reader)?,
+            num_i64: order.read_u16(&mut reader)?,
+            num_f64: order.read_u16(&mut reader)?,
+            num_bytes: order.read_u16(&mut reader)?,
+            num_json: order.read_u16(&mut reader)?,
+            num_null: order.read_u16(&mut reader)?,
+            num_bool: order.read_u16(&mut reader)?,
         })
     }
 
@@ -111,16 +308,143 @@ impl DocHeader {
             + self.num_f64 as usize
             + self.num_bytes as usize
             + self.num_json as usize
+            + self.num_null as usize
+            + self.num_bool as usize
+    }
+
+    #[inline]
+    /// Returns if the document has outlived the given TTL at the time `now`.
+    ///
+    /// Documents never expire if no TTL is given.
+    pub fn is_expired(&self, ttl: Option<u64>, now: u64) -> bool {
+        ttl.is_some_and(|ttl| self.timestamp.saturating_add(ttl) <= now)
     }
 
     /// Reads a set of document fields from a given buffer according to the document header.
+    ///
+    /// Panics if the buffer is smaller than the header describes, use
+    /// [Self::try_read_document_fields] for buffers which may be truncated.
     pub fn read_document_fields<'a>(
         &self,
-        mut doc_buffer: &'a [u8],
+        doc_buffer: &'a [u8],
+        contains_header: bool,
+    ) -> Vec<Field<'a>> {
+        self.read_document_fields_with_width(
+            doc_buffer,
+            contains_header,
+            FieldIdWidth::default(),
+        )
+    }
+
+    /// Reads a set of document fields from a given buffer according to the document header
+    /// where the field IDs were encoded with the given width.
+    ///
+    /// Panics if the buffer is smaller than the header describes, which is also the
+    /// case if the width does not match the document, use
+    /// [Self::try_read_document_fields_with_width] for untrusted input.
+    pub fn read_document_fields_with_width<'a>(
+        &self,
+        doc_buffer: &'a [u8],
+        contains_header: bool,
+        width: FieldIdWidth,
+    ) -> Vec<Field<'a>> {
+        self.try_read_document_fields_with_width(doc_buffer, contains_header, width)
+            .expect("Document is smaller than its header describes")
+    }
+
+    /// Reads a set of document fields from a given buffer according to the document header
+    /// where the document was encoded with [encode_document_with_schema].
+    ///
+    /// If the schema preserves the field order, the fields are returned in the order
+    /// they were originally given in, otherwise they are grouped by their type.
+    /// Any fields which have since been retired from the schema are skipped.
+    ///
+    /// Panics if the buffer is smaller than the header describes, which is also the
+    /// case if the schema does not match the document, use
+    /// [Self::try_read_document_fields_with_schema] for untrusted input.
+    pub fn read_document_fields_with_schema<'a>(
+        &self,
+        doc_buffer: &'a [u8],
         contains_header: bool,
+        schema: &BasicSchema,
     ) -> Vec<Field<'a>> {
+        self.try_read_document_fields_with_schema(doc_buffer, contains_header, schema)
+            .expect("Document is smaller than its header describes")
+    }
+
+    /// Reads a set of document fields from a given buffer according to the document header.
+    ///
+    /// Returns `None` if the buffer is smaller than the header describes.
+    pub fn try_read_document_fields<'a>(
+        &self,
+        doc_buffer: &'a [u8],
+        contains_header: bool,
+    ) -> Option<Vec<Field<'a>>> {
+        self.try_read_document_fields_with_width(
+            doc_buffer,
+            contains_header,
+            FieldIdWidth::default(),
+        )
+    }
+
+    /// Reads a set of document fields from a given buffer according to the document header
+    /// where the field IDs were encoded with the given width.
+    ///
+    /// Returns `None` if the buffer is smaller than the header describes.
+    pub fn try_read_document_fields_with_width<'a>(
+        &self,
+        mut doc_buffer: &'a [u8],
+        contains_header: bool,
+        width: FieldIdWidth,
+    ) -> Option<Vec<Field<'a>>> {
+        self.try_read_fields_with_layout(
+            &mut doc_buffer,
+            contains_header,
+            width,
+            ByteOrder::default(),
+        )
+    }
+
+    /// Reads a set of document fields from a given buffer according to the document header
+    /// where the document was encoded with [encode_document_with_schema].
+    ///
+    /// Returns `None` if the buffer is smaller than the header describes.
+    pub fn try_read_document_fields_with_schema<'a>(
+        &self,
+        mut doc_buffer: &'a [u8],
+        contains_header: bool,
+        schema: &BasicSchema,
+    ) -> Option<Vec<Field<'a>>> {
+        let order = schema.byte_order();
+        let mut fields = self.try_read_fields_with_layout(
+            &mut doc_buffer,
+            contains_header,
+            schema.field_id_width(),
+            order,
+        )?;
+
+        if schema.preserves_field_order() {
+            let mut positioned = fields
+                .into_iter()
+                .map(|field| Some((order.read_u16(&mut doc_buffer)?, field)))
+                .collect::<Option<Vec<_>>>()?;
+            positioned.sort_by_key(|(position, _)| *position);
+            fields = positioned.into_iter().map(|(_, field)| field).collect();
+        }
+
+        fields.retain(|field| !schema.is_retired(field.field_id));
+        Some(fields)
+    }
+
+    fn try_read_fields_with_layout<'a>(
+        &self,
+        buf: &mut &'a [u8],
+        contains_header: bool,
+        width: FieldIdWidth,
+        order: ByteOrder,
+    ) -> Option<Vec<Field<'a>>> {
         if contains_header {
-            doc_buffer = &doc_buffer[DOC_HEADER_SIZE..];
+            *buf = buf.get(DOC_HEADER_SIZE..)?;
         }
 
         let mut fields = Vec::with_capacity(self.num_fields());
@@ -129,21 +453,61 @@ impl DocHeader {
         read_fields(
             ValueType::String,
             self.num_string,
-            &mut doc_buffer,
+            width,
+            order,
+            buf,
             &mut fields,
-        );
-        read_fields(ValueType::U64, self.num_u64, &mut doc_buffer, &mut fields);
-        read_fields(ValueType::I64, self.num_i64, &mut doc_buffer, &mut fields);
-        read_fields(ValueType::F64, self.num_f64, &mut doc_buffer, &mut fields);
+        )?;
+        read_fields(ValueType::U64, self.num_u64, width, order, buf, &mut fields)?;
+        read_fields(ValueType::I64, self.num_i64, width, order, buf, &mut fields)?;
+        read_fields(ValueType::F64, self.num_f64, width, order, buf, &mut fields)?;
         read_fields(
             ValueType::Bytes,
             self.num_bytes,
-            &mut doc_buffer,
+            width,
+            order,
+            buf,
             &mut fields,
-        );
-        read_fields(ValueType::Json, self.num_json, &mut doc_buffer, &mut fields);
+        )?;
+        read_fields(
+            ValueType::Json,
+            self.num_json,
+            width,
+            order,
+            buf,
+            &mut fields,
+        )?;
+        read_fields(
+            ValueType::Null,
+            self.num_null,
+            width,
+            order,
+            buf,
+            &mut fields,
+        )?;
+        read_fields(
+            ValueType::Bool,
+            self.num_bool,
+            width,
+            order,
+            buf,
+            &mut fields,
+        )?;
 
-        fields
+        Some(fields)
+    }
+
+    #[cfg(test)]
+    /// Reads the header from the buffer and asserts it is written back identically.
+    pub(crate) fn assert_roundtrip(buf: &[u8]) -> Self {
+        let header = Self::try_read_from(buf).expect("Read header");
+
+        let mut written = Vec::new();
+        header.write_to(&mut written);
+        assert_eq!(written[..], buf[..DOC_HEADER_SIZE]);
+        assert_eq!(Self::try_read_from(&written).as_ref(), Some(&header));
+
+        header
     }
 
     /// Increments a field type's count based on the provided value type.
@@ -167,7 +531,12 @@ impl DocHeader {
             ValueType::Json => {
                 self.num_json += 1;
             },
-            ValueType::Null => {},
+            ValueType::Null => {
+                self.num_null += 1;
+            },
+            ValueType::Bool => {
+                self.num_bool += 1;
+            },
         }
     }
 }
@@ -187,221 +556,788 @@ pub fn encode_document_to<'a: 'b, 'b, S: AsRef<str> + 'b>(
     num_fields: usize,
     fields: impl IntoIterator<Item = (&'b S, &'b DocField<'a>)>,
     hash_key: Option<FieldId>,
-) -> u64 {
-    let mut hasher = cityhash_sys::CityHash64Hasher::default();
+) -> DocDigest {
+    encode_document_with_hasher(
+        buffer,
+        ts,
+        fields_lookup,
+        num_fields,
+        fields,
+        hash_key,
+        cityhash_sys::CityHash64Hasher::default(),
+    )
+}
 
-    let mut header = DocHeader::new(ts);
-    let mut encoding_fields = Vec::with_capacity(num_fields);
-    for (field_name, value) in fields {
-        if let Some(field_id) = fields_lookup.get(field_name.as_ref()) {
-            encoding_fields.push((*field_id, value));
-            header.increment_count_on_type(value.value_type());
-        }
-    }
+/// Encodes a document value into a provided value using a specific digest hasher.
+///
+/// This behaves the same as [encode_document_to] but allows the caller to select
+/// the hash algorithm. Nothing checks the hasher matches the algorithm recorded
+/// in a schema, use [encode_document_with_schema] to have it selected for you.
+pub fn encode_document_with_hasher<'a: 'b, 'b, S: AsRef<str> + 'b, H: DocHasher>(
+    buffer: &mut Vec<u8>,
+    ts: u64,
+    fields_lookup: &BTreeMap<String, FieldId>,
+    num_fields: usize,
+    fields: impl IntoIterator<Item = (&'b S, &'b DocField<'a>)>,
+    hash_key: Option<FieldId>,
+    hasher: H,
+) -> DocDigest {
+    let config = EncodeConfig {
+        hash_key,
+        field_id_width: FieldIdWidth::default(),
+        byte_order: ByteOrder::default(),
+        preserve_field_order: false,
+    };
+    let values = flatten_fields(resolve_fields(fields_lookup, fields));
+    encode_resolved_values(buffer, ts, num_fields, values, config, hasher)
+        .expect("Any document can be encoded with the default config")
+}
 
-    // We must sort the values so that they are correctly organised when reading.
-    encoding_fields.sort_by_key(|(_, v)| v.value_type());
+/// Encodes a document value into a provided value according to the given schema.
+///
+/// The field lookup, hash key, hash algorithm, field ID width and byte order are
+/// all taken from the schema. Documents encoded this way must be read with
+/// [DocHeader::read_document_fields_with_schema].
+///
+/// Field names are resolved with [BasicSchema::resolve_field], so aliases are
+/// accepted and fields which have been retired are dropped.
+///
+/// If the schema preserves the field order, the order recorded is the order the
+/// fields are iterated in. Maps such as a `BTreeMap` iterate their fields
+/// alphabetically, so pass an ordered sequence of pairs, like a slice, to record
+/// the order the fields were inserted in.
+///
+/// An error is returned if the document cannot be encoded with the schema's
+/// settings, in which case nothing is written to the buffer.
+pub fn encode_document_with_schema<'a: 'b, 'b, S: AsRef<str> + 'b>(
+    buffer: &mut Vec<u8>,
+    ts: u64,
+    schema: &BasicSchema,
+    fields: impl IntoIterator<Item = (&'b S, &'b DocField<'a>)>,
+) -> Result<DocDigest, EncodeError> {
+    let num_fields = schema.fields().len();
+    let values = flatten_fields(fields.into_iter().filter_map(|(field_name, value)| {
+        schema
+            .resolve_field(field_name.as_ref())
+            .map(|field_id| (field_id, value))
+    }));
+    encode_values_with_schema(buffer, ts, schema, num_fields, values)
+}
 
-    header.write_to(buffer);
-    for (field_id, field) in encoding_fields {
-        let should_hash = hash_key.map(|v| v == field_id).unwrap_or(true);
-        encode_field(buffer, field_id, field, &mut hasher, should_hash);
+/// Encodes a JSON document into a provided value according to the given schema.
+///
+/// The JSON object is walked directly rather than first being converted into a
+/// map of [DocField]s, and produces the same output as [encode_document_with_schema]
+/// would for the equivalent document. Unknown fields are dropped, and values which
+/// have no equivalent value type, namely nested arrays, are skipped.
+/// If the value is not an object, an empty document is encoded.
+///
+/// If the schema preserves the field order, the order recorded is the order of the
+/// JSON object's keys, which `serde_json` sorts alphabetically unless its
+/// `preserve_order` feature is enabled.
+pub fn encode_json_value_to(
+    buffer: &mut Vec<u8>,
+    ts: u64,
+    schema: &BasicSchema,
+    value: &Value,
+) -> Result<DocDigest, EncodeError> {
+    let mut values = Vec::new();
+    for (field_name, value) in value.as_object().into_iter().flatten() {
+        let Some(field_id) = schema.resolve_field(field_name) else {
+            continue;
+        };
+
+        match value {
+            Value::Array(elements) => values.extend(
+                elements
+                    .iter()
+                    .filter_map(json_to_doc_value)
+                    .map(|value| (field_id, value)),
+            ),
+            value => {
+                values.extend(json_to_doc_value(value).map(|value| (field_id, value)))
+            },
+        }
     }
 
-    hasher.finish()
+    let num_fields = values.len();
+    let values = values.iter().map(|(field_id, value)| (*field_id, value));
+    encode_values_with_schema(buffer, ts, schema, num_fields, values)
 }
 
-#[derive(Debug, thiserror::Error)]
-#[error("Unable to deserialize field data into value with type: {0:?}")]
-pub struct Corrupted(ValueType);
-
-/// Attempts to convert the raw field into a doc value.
+/// Converts a single JSON value into its equivalent doc value.
 ///
-/// This will not allocated any values apart from JSON values which
-/// must be owned.
-pub fn field_to_value(field: Field) -> Result<DocValue, Corrupted> {
-    let val = match field.value_type {
-        ValueType::String => {
-            let data = simdutf8::basic::from_utf8(field.value)
-                .map_err(|_| Corrupted(field.value_type))?;
-            DocValue::from(data)
-        },
-        ValueType::U64 => {
-            let data = field
-                .value
-                .try_into()
-                .map_err(|_| Corrupted(field.value_type))?;
-            DocValue::from(u64::from_le_bytes(data))
-        },
-        ValueType::I64 => {
-            let data = field
-                .value
-                .try_into()
-                .map_err(|_| Corrupted(field.value_type))?;
-            DocValue::from(i64::from_le_bytes(data))
-        },
-        ValueType::F64 => {
-            let data = field
-                .value
-                .try_into()
-                .map_err(|_| Corrupted(field.value_type))?;
-            DocValue::from(f64::from_le_bytes(data))
-        },
-        ValueType::Bytes => DocValue::Bytes(Cow::Borrowed(field.value)),
-        ValueType::Json => {
-            let data = serde_cbor::from_slice(field.value)
-                .map_err(|_| Corrupted(field.value_type))?;
-            DocValue::Json(data)
+/// JSON objects must be cloned as the doc value holds an owned map.
+fn json_to_doc_value(value: &Value) -> Option<DocValue<'_>> {
+    let value = match value {
+        Value::Null => DocValue::Null,
+        Value::Bool(v) => DocValue::Bool(*v),
+        Value::Number(n) => {
+            if let Some(v) = n.as_u64() {
+                DocValue::U64(v)
+            } else if let Some(v) = n.as_i64() {
+                DocValue::I64(v)
+            } else {
+                DocValue::F64(n.as_f64()?)
+            }
         },
-        ValueType::Null => DocValue::Null,
+        Value::String(v) => DocValue::String(Cow::Borrowed(v)),
+        Value::Object(v) => DocValue::Json(v.clone()),
+        Value::Array(_) => return None,
     };
 
-    Ok(val)
+    Some(value)
 }
 
-#[inline]
-/// Writes a single doc field into the buffer.
-///
-/// This is done in a log-format so multi-value fields
-/// are another entry into the log.
-fn encode_field(
+fn encode_values_with_schema<'a: 'b, 'b>(
     buffer: &mut Vec<u8>,
-    field_id: FieldId,
-    field: &DocField,
-    hasher: &mut cityhash_sys::CityHash64Hasher,
-    should_hash: bool,
-) {
-    match field {
-        DocField::Single(value) => {
-            encode_value(buffer, field_id, value, hasher, should_hash)
-        },
-        DocField::Many(values) => {
-            for value in values {
-                // We assume the values in the array are all the same type.
-                // Otherwise the decoder may not be able to decode the value correctly.
-                encode_value(buffer, field_id, value, hasher, should_hash)
-            }
+    ts: u64,
+    schema: &BasicSchema,
+    num_fields: usize,
+    values: impl IntoIterator<Item = (FieldId, &'b DocValue<'a>)>,
+) -> Result<DocDigest, EncodeError> {
+    let config = EncodeConfig {
+        hash_key: schema.hash_key(),
+        field_id_width: schema.field_id_width(),
+        byte_order: schema.byte_order(),
+        preserve_field_order: schema.preserves_field_order(),
+    };
+
+    match schema.hash_algorithm() {
+        HashAlgorithm::CityHash64 => encode_resolved_values(
+            buffer,
+            ts,
+            num_fields,
+            values,
+            config,
+            cityhash_sys::CityHash64Hasher::default(),
+        ),
+        #[cfg(feature = "xxhash")]
+        HashAlgorithm::Xxh3 => encode_resolved_values(
+            buffer,
+            ts,
+            num_fields,
+            values,
+            config,
+            xxhash_rust::xxh3::Xxh3::new(),
+        ),
+        #[cfg(not(feature = "xxhash"))]
+        algorithm @ HashAlgorithm::Xxh3 => {
+            Err(EncodeError::UnsupportedHashAlgorithm(algorithm))
         },
     }
 }
 
-#[inline]
-/// Writes a single doc value into the buffer.
-fn encode_value(
+/// Encodes a document from values which are already resolved to their field IDs.
+///
+/// This skips the field name lookup entirely and trusts the caller's IDs.
+/// Multi-value fields are provided as one entry per value, using the same
+/// field ID, in which case the output is identical to [encode_document_to]
+/// for the same logical document.
+pub fn encode_document_by_id<'a: 'b, 'b>(
     buffer: &mut Vec<u8>,
-    field_id: FieldId,
-    value: &DocValue,
-    hasher: &mut cityhash_sys::CityHash64Hasher,
-    should_hash: bool,
-) {
-    let start = buffer.len();
-    buffer.extend_from_slice(&field_id.to_le_bytes());
+    ts: u64,
+    values: impl IntoIterator<Item = (FieldId, &'b DocValue<'a>)>,
+    hash_key: Option<FieldId>,
+) -> DocDigest {
+    let values = values.into_iter();
+    let config = EncodeConfig {
+        hash_key,
+        field_id_width: FieldIdWidth::default(),
+        byte_order: ByteOrder::default(),
+        preserve_field_order: false,
+    };
 
-    match value {
-        DocValue::U64(v) => buffer.extend_from_slice(&v.to_le_bytes()),
-        DocValue::I64(v) => buffer.extend_from_slice(&v.to_le_bytes()),
-        DocValue::F64(v) => buffer.extend_from_slice(&v.to_le_bytes()),
-        DocValue::String(v) => {
-            buffer.extend_from_slice(&(v.len() as FieldLen).to_le_bytes());
-            buffer.extend_from_slice(v.as_bytes());
-        },
-        DocValue::Bytes(v) => {
-            buffer.extend_from_slice(&(v.len() as FieldLen).to_le_bytes());
-            buffer.extend_from_slice(v);
-        },
-        DocValue::Json(v) => {
-            let v = serde_cbor::to_vec(v).expect("Encode valid JSON.");
-            buffer.extend_from_slice(&(v.len() as FieldLen).to_le_bytes());
-            buffer.extend_from_slice(&v);
-        },
-        DocValue::Null => {},
+    encode_resolved_values(
+        buffer,
+        ts,
+        values.size_hint().0,
+        values,
+        config,
+        cityhash_sys::CityHash64Hasher::default(),
+    )
+    .expect("Any document can be encoded with the default config")
+}
+
+/// Computes the digest of a document without producing an encoded document.
+///
+/// The digest matches the one [encode_document_to] returns for the same input,
+/// allowing duplicate documents to be detected before deciding to store them.
+///
+/// Nothing is encoded or allocated, each value is fed straight to the hasher.
+/// The fields are walked several times, so their iterator must be cheap to clone.
+pub fn document_digest<'a: 'b, 'b, S: AsRef<str> + 'b, I>(
+    fields_lookup: &BTreeMap<String, FieldId>,
+    fields: I,
+    hash_key: Option<FieldId>,
+) -> DocDigest
+where
+    I: IntoIterator<Item = (&'b S, &'b DocField<'a>)>,
+    I::IntoIter: Clone,
+{
+    let config = EncodeConfig {
+        hash_key,
+        field_id_width: FieldIdWidth::default(),
+        byte_order: ByteOrder::default(),
+        preserve_field_order: false,
+    };
+
+    let values = fields
+        .into_iter()
+        .filter_map(|(field_name, field)| {
+            fields_lookup
+                .get(field_name.as_ref())
+                .map(|field_id| (*field_id, field))
+        })
+        .flat_map(|(field_id, field)| field.iter().map(move |value| (field_id, value)));
+
+    digest_values(values, &config, cityhash_sys::CityHash64Hasher::default())
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Document contains fields which are not part of the schema: {0:?}")]
+pub struct UnknownFields(pub Vec<String>);
+
+/// Encodes a document value into a provided value, rejecting unknown fields.
+///
+/// This behaves the same as [encode_document_to] except any fields which are
+/// not within the lookup cause an error listing the unknown field names rather
+/// than being silently dropped. Nothing is written to the buffer on error.
+pub fn encode_document_strict<'a: 'b, 'b, S: AsRef<str> + 'b>(
+    buffer: &mut Vec<u8>,
+    ts: u64,
+    fields_lookup: &BTreeMap<String, FieldId>,
+    num_fields: usize,
+    fields: impl IntoIterator<Item = (&'b S, &'b DocField<'a>)>,
+    hash_key: Option<FieldId>,
+) -> Result<DocDigest, UnknownFields> {
+    let mut resolved = Vec::with_capacity(num_fields);
+    let mut unknown = Vec::new();
+    for (field_name, value) in fields {
+        match fields_lookup.get(field_name.as_ref()) {
+            Some(field_id) => resolved.push((*field_id, value)),
+            None => unknown.push(field_name.as_ref().to_string()),
+        }
     }
 
-    if should_hash {
-        hasher.write(&buffer[start..]);
+    if !unknown.is_empty() {
+        return Err(UnknownFields(unknown));
     }
-}
 
-pub struct Field<'a> {
-    /// The value type of the field.
-    pub value_type: ValueType,
-    /// The ID of the field.
-    pub field_id: FieldId,
-    /// The value of the field in bytes.
-    pub value: &'a [u8],
+    let config = EncodeConfig {
+        hash_key,
+        field_id_width: FieldIdWidth::default(),
+        byte_order: ByteOrder::default(),
+        preserve_field_order: false,
+    };
+    Ok(encode_resolved_values(
+        buffer,
+        ts,
+        num_fields,
+        flatten_fields(resolved),
+        config,
+        cityhash_sys::CityHash64Hasher::default(),
+    )
+    .expect("Any document can be encoded with the default config"))
 }
 
-#[inline]
-fn read_u16_le(buffer: &mut &[u8]) -> Option<u16> {
-    let (int_bytes, rest) = buffer.split_at(size_of::<u16>());
-    *buffer = rest;
+#[derive(Debug, thiserror::Error)]
+#[error("Document contains multiple values for single-valued fields: {0:?}")]
+pub struct DuplicateFields(pub Vec<FieldId>);
 
-    let slice = int_bytes.try_into().ok()?;
-    Some(u16::from_le_bytes(slice))
-}
+/// Encodes a document from values which are already resolved to their field IDs
+/// according to the given schema, rejecting duplicate single-valued fields.
+///
+/// This behaves the same as [encode_document_by_id] except the settings are
+/// taken from the schema, and any field which is not declared as multi-valued
+/// but is given more than one value causes an error listing those field IDs.
+/// Nothing is written to the buffer on error.
+pub fn encode_document_by_id_strict<'a: 'b, 'b>(
+    buffer: &mut Vec<u8>,
+    ts: u64,
+    schema: &BasicSchema,
+    values: impl IntoIterator<Item = (FieldId, &'b DocValue<'a>)>,
+) -> Result<DocDigest, EncodeError> {
+    let values = values.into_iter().collect::<Vec<_>>();
 
-#[inline]
-fn read_timestamp(buffer: &mut &[u8]) -> Option<u64> {
-    let (int_bytes, rest) = buffer.split_at(size_of::<u64>());
-    *buffer = rest;
+    let mut seen = BTreeMap::new();
+    for (field_id, _) in values.iter() {
+        *seen.entry(*field_id).or_insert(0_usize) += 1;
+    }
+
+    let duplicates = seen
+        .into_iter()
+        .filter(|(field_id, count)| {
+            let is_multi = schema
+                .field_infos()
+                .get(*field_id as usize)
+                .map(|info| info.is_multi())
+                .unwrap_or(false);
+            *count > 1 && !is_multi
+        })
+        .map(|(field_id, _)| field_id)
+        .collect::<Vec<_>>();
+
+    if !duplicates.is_empty() {
+        return Err(DuplicateFields(duplicates).into());
+    }
 
-    let slice = int_bytes.try_into().ok()?;
-    Some(u64::from_le_bytes(slice))
+    let num_fields = values.len();
+    encode_values_with_schema(buffer, ts, schema, num_fields, values)
+}
+
+#[derive(Debug, thiserror::Error)]
+/// The reasons a document cannot be encoded according to its schema.
+pub enum EncodeError {
+    #[error("Hash algorithm {0:?} is not enabled, enable its feature to encode documents with it")]
+    UnsupportedHashAlgorithm(HashAlgorithm),
+    #[error("Field ID {field_id} cannot be encoded with the schema's field ID width of {width:?}")]
+    FieldIdTooLarge {
+        field_id: FieldId,
+        width: FieldIdWidth,
+    },
+    #[error(
+        "Document has more values than can be positioned while preserving field order"
+    )]
+    TooManyValues,
+    #[error(transparent)]
+    DuplicateFields(#[from] DuplicateFields),
+}
+
+/// The per-schema settings used when encoding a document.
+struct EncodeConfig {
+    hash_key: Option<FieldId>,
+    field_id_width: FieldIdWidth,
+    byte_order: ByteOrder,
+    preserve_field_order: bool,
+}
+
+/// Resolves the field names to their IDs, dropping any unknown fields.
+fn resolve_fields<'a: 'b, 'b: 'l, 'l, S: AsRef<str> + 'b>(
+    fields_lookup: &'l BTreeMap<String, FieldId>,
+    fields: impl IntoIterator<Item = (&'b S, &'b DocField<'a>)> + 'l,
+) -> impl Iterator<Item = (FieldId, &'b DocField<'a>)> + 'l {
+    fields.into_iter().filter_map(|(field_name, value)| {
+        fields_lookup
+            .get(field_name.as_ref())
+            .map(|field_id| (*field_id, value))
+    })
+}
+
+/// Flattens the fields into their individual values.
+///
+/// Multi-value fields are encoded as one entry per value.
+fn flatten_fields<'a: 'b, 'b>(
+    fields: impl IntoIterator<Item = (FieldId, &'b DocField<'a>)>,
+) -> impl Iterator<Item = (FieldId, &'b DocValue<'a>)> {
+    fields
+        .into_iter()
+        .flat_map(|(field_id, field)| field.iter().map(move |value| (field_id, value)))
+}
+
+fn encode_resolved_values<'a: 'b, 'b, H: DocHasher>(
+    buffer: &mut Vec<u8>,
+    ts: u64,
+    num_fields: usize,
+    values: impl IntoIterator<Item = (FieldId, &'b DocValue<'a>)>,
+    config: EncodeConfig,
+    hasher: H,
+) -> Result<DocDigest, EncodeError> {
+    let mut header = DocHeader::new(ts);
+    let mut encoding_values = Vec::with_capacity(num_fields);
+    for (position, (field_id, value)) in values.into_iter().enumerate() {
+        // Checked before anything is written, so the buffer is left untouched.
+        let width = config.field_id_width;
+        if field_id > width.max_field_id() {
+            return Err(EncodeError::FieldIdTooLarge { field_id, width });
+        }
+        if config.preserve_field_order && position > u16::MAX as usize {
+            return Err(EncodeError::TooManyValues);
+        }
+
+        encoding_values.push((position, field_id, value));
+        header.increment_count_on_type(value.value_type());
+    }
+
+    // We must sort the values so that they are correctly organised when reading.
+    encoding_values.sort_by_key(|(_, _, v)| v.value_type());
+
+    header.write_to_with_order(buffer, config.byte_order);
+    for (_, field_id, value) in encoding_values.iter() {
+        encode_value(buffer, *field_id, &config, value);
+    }
+
+    // The original position of each value follows the values themselves,
+    // so it is not part of the document's digest. Each position has been
+    // checked to fit within a `u16` above.
+    if config.preserve_field_order {
+        for (position, ..) in &encoding_values {
+            config.byte_order.write_u16(buffer, *position as u16);
+        }
+    }
+
+    let values = encoding_values
+        .iter()
+        .map(|(_, field_id, value)| (*field_id, *value));
+    Ok(digest_values(values, &config, hasher))
+}
+
+/// The order value types are encoded in, see [DocHeader::read_document_fields].
+const VALUE_TYPE_ORDER: [ValueType; 8] = [
+    ValueType::String,
+    ValueType::U64,
+    ValueType::I64,
+    ValueType::F64,
+    ValueType::Bytes,
+    ValueType::Json,
+    ValueType::Null,
+    ValueType::Bool,
+];
+
+/// Computes the digest of a document's values without encoding them.
+///
+/// Values are hashed grouped by their value type, in the order they are encoded.
+/// The values of a field which is given more than once are each hashed with their
+/// own hasher and summed, so the digest does not depend on the order they were
+/// given in. The sum is hashed in place of the field's first value.
+/// Single-value fields are hashed in the order they were given.
+fn digest_values<'a: 'b, 'b, H: DocHasher>(
+    values: impl Iterator<Item = (FieldId, &'b DocValue<'a>)> + Clone,
+    config: &EncodeConfig,
+    mut hasher: H,
+) -> DocDigest {
+    let hash_key = config.hash_key;
+    let values = values
+        .filter(move |(field_id, _)| hash_key.map(|v| v == *field_id).unwrap_or(true));
+
+    let mut seen = FieldIdSet::default();
+    let mut repeated = FieldIdSet::default();
+    for (field_id, _) in values.clone() {
+        if !seen.insert(field_id) {
+            repeated.insert(field_id);
+        }
+    }
+
+    for value_type in VALUE_TYPE_ORDER {
+        let typed = values
+            .clone()
+            .filter(move |(_, value)| value.value_type() == value_type);
+        for (field_id, value) in typed {
+            if !repeated.contains(field_id) {
+                hash_value(&mut hasher, field_id, config, value);
+                continue;
+            }
+
+            // Only the first value of the field is left seen.
+            if !seen.remove(field_id) {
+                continue;
+            }
+
+            let sum = values.clone().filter(|(id, _)| *id == field_id).fold(
+                0_u64,
+                |sum, (_, value)| {
+                    let mut value_hasher = H::default();
+                    hash_value(&mut value_hasher, field_id, config, value);
+                    sum.wrapping_add(value_hasher.finalize_u64())
+                },
+            );
+            config
+                .field_id_width
+                .hash_into(&mut hasher, field_id, config.byte_order);
+            hasher.update(&sum.to_le_bytes());
+        }
+    }
+
+    DocDigest(hasher.finalize_u64())
+}
+
+/// Feeds a single value to the hasher exactly as [encode_value] lays it out.
+///
+/// JSON values are self-delimiting once encoded, so their length is not hashed,
+/// allowing them to be streamed into the hasher.
+fn hash_value<H: DocHasher>(
+    hasher: &mut H,
+    field_id: FieldId,
+    config: &EncodeConfig,
+    value: &DocValue,
+) {
+    let order = config.byte_order;
+    config.field_id_width.hash_into(hasher, field_id, order);
+
+    match value {
+        DocValue::U64(v) => hasher.update(&order.encode_u64(*v)),
+        DocValue::I64(v) => hasher.update(&order.encode_u64(*v as u64)),
+        DocValue::F64(v) => hasher.update(&order.encode_u64(v.to_bits())),
+        DocValue::Bool(v) => hasher.update(&[*v as u8]),
+        DocValue::String(v) => {
+            hasher.update(&order.encode_u32(v.len() as FieldLen));
+            hasher.update(v.as_bytes());
+        },
+        DocValue::Bytes(v) => {
+            hasher.update(&order.encode_u32(v.len() as FieldLen));
+            hasher.update(v);
+        },
+        DocValue::Json(v) => {
+            serde_cbor::to_writer(HashWriter(hasher), v).expect("Encode valid JSON.")
+        },
+        DocValue::Null => {},
+    }
+}
+
+/// Writes bytes straight into a hasher.
+struct HashWriter<'a, H>(&'a mut H);
+
+impl<H: DocHasher> io::Write for HashWriter<'_, H> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+/// A fixed size set of field IDs, used to track fields without allocating.
+struct FieldIdSet([u64; (FieldId::MAX as usize + 1) / 64]);
+
+impl Default for FieldIdSet {
+    fn default() -> Self {
+        Self([0; (FieldId::MAX as usize + 1) / 64])
+    }
+}
+
+impl FieldIdSet {
+    /// Adds the field ID to the set, returning if it was not already present.
+    fn insert(&mut self, field_id: FieldId) -> bool {
+        let (word, bit) = Self::position(field_id);
+        let inserted = self.0[word] & bit == 0;
+        self.0[word] |= bit;
+        inserted
+    }
+
+    /// Removes the field ID from the set, returning if it was present.
+    fn remove(&mut self, field_id: FieldId) -> bool {
+        let (word, bit) = Self::position(field_id);
+        let removed = self.0[word] & bit != 0;
+        self.0[word] &= !bit;
+        removed
+    }
+
+    fn contains(&self, field_id: FieldId) -> bool {
+        let (word, bit) = Self::position(field_id);
+        self.0[word] & bit != 0
+    }
+
+    fn position(field_id: FieldId) -> (usize, u64) {
+        (field_id as usize / 64, 1 << (field_id % 64))
+    }
+}
+
+/// Re-encodes an existing document with a new timestamp.
+///
+/// Only the timestamp within the document header is replaced, the field
+/// values are copied as-is. The timestamp is not part of the document's digest
+/// so any previously computed digest remains valid.
+///
+/// Returns `None` if the buffer is too small to contain a document header.
+pub fn rewrite_timestamp(doc_buf: &[u8], new_ts: u64) -> Option<Vec<u8>> {
+    rewrite_timestamp_with_order(doc_buf, new_ts, ByteOrder::default())
+}
+
+/// Re-encodes an existing document with a new timestamp where the document was
+/// encoded with the given byte order.
+pub fn rewrite_timestamp_with_order(
+    doc_buf: &[u8],
+    new_ts: u64,
+    order: ByteOrder,
+) -> Option<Vec<u8>> {
+    if doc_buf.len() < DOC_HEADER_SIZE {
+        return None;
+    }
+
+    let mut ts = Vec::with_capacity(size_of::<u64>());
+    order.write_u64(&mut ts, new_ts);
+
+    let mut buffer = doc_buf.to_vec();
+    buffer[..size_of::<u64>()].copy_from_slice(&ts);
+    Some(buffer)
+}
+
+/// Skips any encoded documents which have expired at the time `now`.
+///
+/// Expiry is decided by the schema's TTL, see [DocHeader::is_expired], and each
+/// document's header is read using the schema's byte order. Buffers which are too
+/// small to contain a document header are passed through, so the caller's reader
+/// can report them.
+pub fn skip_expired<'a>(
+    docs: impl IntoIterator<Item = &'a [u8]>,
+    schema: &BasicSchema,
+    now: u64,
+) -> impl Iterator<Item = &'a [u8]> {
+    let ttl = schema.ttl();
+    let order = schema.byte_order();
+    docs.into_iter().filter(move |doc| {
+        DocHeader::try_read_from_with_order(doc, order)
+            .map(|header| !header.is_expired(ttl, now))
+            .unwrap_or(true)
+    })
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Unable to deserialize field data into value with type: {0:?}")]
+pub struct Corrupted(ValueType);
+
+/// Attempts to convert the raw field into a doc value.
+///
+/// This will not allocated any values apart from JSON values which
+/// must be owned.
+pub fn field_to_value(field: Field) -> Result<DocValue, Corrupted> {
+    let val = match field.value_type {
+        ValueType::String => {
+            let data = simdutf8::basic::from_utf8(field.value)
+                .map_err(|_| Corrupted(field.value_type))?;
+            DocValue::from(data)
+        },
+        ValueType::U64 => DocValue::from(decode_u64(&field)?),
+        ValueType::I64 => DocValue::from(decode_u64(&field)? as i64),
+        ValueType::F64 => DocValue::from(f64::from_bits(decode_u64(&field)?)),
+        ValueType::Bytes => DocValue::Bytes(Cow::Borrowed(field.value)),
+        ValueType::Json => {
+            let data = serde_cbor::from_slice(field.value)
+                .map_err(|_| Corrupted(field.value_type))?;
+            DocValue::Json(data)
+        },
+        ValueType::Null => DocValue::Null,
+        ValueType::Bool => match field.value {
+            [0] => DocValue::Bool(false),
+            [1] => DocValue::Bool(true),
+            _ => return Err(Corrupted(field.value_type)),
+        },
+    };
+
+    Ok(val)
+}
+
+#[inline]
+/// Decodes a fixed size 64 bit field value using the field's byte order.
+fn decode_u64(field: &Field) -> Result<u64, Corrupted> {
+    field
+        .byte_order
+        .decode_u64(field.value)
+        .ok_or(Corrupted(field.value_type))
+}
+
+#[inline]
+/// Writes a single doc value into the buffer, returning the range it was written to.
+fn encode_value(
+    buffer: &mut Vec<u8>,
+    field_id: FieldId,
+    config: &EncodeConfig,
+    value: &DocValue,
+) {
+    let order = config.byte_order;
+    config.field_id_width.write_to(buffer, field_id, order);
+
+    match value {
+        DocValue::U64(v) => order.write_u64(buffer, *v),
+        DocValue::I64(v) => order.write_u64(buffer, *v as u64),
+        DocValue::F64(v) => order.write_u64(buffer, v.to_bits()),
+        DocValue::Bool(v) => buffer.push(*v as u8),
+        DocValue::String(v) => {
+            order.write_u32(buffer, v.len() as FieldLen);
+            buffer.extend_from_slice(v.as_bytes());
+        },
+        DocValue::Bytes(v) => {
+            order.write_u32(buffer, v.len() as FieldLen);
+            buffer.extend_from_slice(v);
+        },
+        DocValue::Json(v) => {
+            let v = serde_cbor::to_vec(v).expect("Encode valid JSON.");
+            order.write_u32(buffer, v.len() as FieldLen);
+            buffer.extend_from_slice(&v);
+        },
+        // Null values are only made up of their field ID.
+        DocValue::Null => {},
+    }
+}
+
+pub struct Field<'a> {
+    /// The value type of the field.
+    pub value_type: ValueType,
+    /// The ID of the field.
+    pub field_id: FieldId,
+    /// The value of the field in bytes.
+    pub value: &'a [u8],
+    /// The byte order the value was encoded with.
+    pub byte_order: ByteOrder,
 }
 
 #[inline]
 /// Reads a set of field entries from a given buffer according to the value type and
 /// the number of fields that are supposed to exist for that type.
 ///
-/// Panics if there are fewer entries than specified.
+/// Returns `None` if there are fewer entries than specified.
 fn read_fields<'a>(
     value_type: ValueType,
     num: u16,
+    width: FieldIdWidth,
+    order: ByteOrder,
     buffer: &mut &'a [u8],
     output: &mut Vec<Field<'a>>,
-) {
+) -> Option<()> {
     for _ in 0..num {
-        let (field_id_bytes, rest) = buffer.split_at(size_of::<FieldId>());
-        *buffer = rest;
-
-        let slice = field_id_bytes
-            .try_into()
-            .expect("Read correct number of bytes but failed to cast into array.");
-        let field_id = FieldId::from_le_bytes(slice);
+        let field_id = width.read_from(buffer, order)?;
         match value_type {
             ValueType::String => {
-                read_var_length_field(value_type, field_id, buffer, output)
+                read_var_length_field(value_type, field_id, order, buffer, output)?
             },
             ValueType::U64 => read_known_length_field(
                 value_type,
                 field_id,
+                order,
                 buffer,
                 output,
                 size_of::<u64>(),
-            ),
+            )?,
             ValueType::I64 => read_known_length_field(
                 value_type,
                 field_id,
+                order,
                 buffer,
                 output,
                 size_of::<i64>(),
-            ),
+            )?,
             ValueType::F64 => read_known_length_field(
                 value_type,
                 field_id,
+                order,
                 buffer,
                 output,
                 size_of::<f64>(),
-            ),
+            )?,
             ValueType::Bytes => {
-                read_var_length_field(value_type, field_id, buffer, output)
+                read_var_length_field(value_type, field_id, order, buffer, output)?
             },
             ValueType::Json => {
-                read_var_length_field(value_type, field_id, buffer, output)
+                read_var_length_field(value_type, field_id, order, buffer, output)?
+            },
+            ValueType::Null => {
+                read_known_length_field(value_type, field_id, order, buffer, output, 0)?
             },
-            ValueType::Null => {},
+            ValueType::Bool => read_known_length_field(
+                value_type,
+                field_id,
+                order,
+                buffer,
+                output,
+                size_of::<bool>(),
+            )?,
         }
     }
+
+    Some(())
 }
 
 #[inline]
@@ -412,18 +1348,20 @@ fn read_fields<'a>(
 fn read_var_length_field<'a>(
     value_type: ValueType,
     field_id: FieldId,
+    order: ByteOrder,
     buffer: &mut &'a [u8],
     output: &mut Vec<Field<'a>>,
-) {
-    let (field_len_bytes, rest) = buffer.split_at(size_of::<FieldLen>());
-    *buffer = rest;
-
-    let slice = field_len_bytes
-        .try_into()
-        .expect("Read correct number of bytes but failed to cast into array.");
-    let field_len = FieldLen::from_le_bytes(slice);
+) -> Option<()> {
+    let field_len = order.read_u32(buffer)?;
 
-    read_known_length_field(value_type, field_id, buffer, output, field_len as usize);
+    read_known_length_field(
+        value_type,
+        field_id,
+        order,
+        buffer,
+        output,
+        field_len as usize,
+    )
 }
 
 #[inline]
@@ -431,24 +1369,28 @@ fn read_var_length_field<'a>(
 fn read_known_length_field<'a>(
     value_type: ValueType,
     field_id: FieldId,
+    order: ByteOrder,
     buffer: &mut &'a [u8],
     output: &mut Vec<Field<'a>>,
     len: usize,
-) {
-    let (value, rest) = buffer.split_at(len);
-    *buffer = rest;
+) -> Option<()> {
+    let value = buffer.get(..len)?;
+    *buffer = &buffer[len..];
 
     output.push(Field {
         value_type,
         field_id,
         value,
+        byte_order: order,
     });
+    Some(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::doc_values;
+    use crate::schema::FieldInfo;
+    use crate::{doc_values, ReferencingDoc};
 
     fn get_lookup() -> BTreeMap<String, FieldId> {
         let mut fields = BTreeMap::new();
@@ -468,7 +1410,7 @@ mod tests {
 
         let mut output = Vec::new();
         encode_document_to(&mut output, 0, &get_lookup(), values.len(), &values, None);
-        assert_eq!(output.len(), 51);
+        assert_eq!(output.len(), 55);
     }
 
     #[test]
@@ -482,16 +1424,16 @@ mod tests {
         dbg!(size_of::<DocHeader>());
         let mut output = Vec::new();
         encode_document_to(&mut output, 0, &get_lookup(), values.len(), &values, None);
-        assert_eq!(output.len(), 51);
+        assert_eq!(output.len(), 55);
 
-        let header = DocHeader::try_read_from(&output).expect("Read header");
-        assert_eq!(header.timestamp, 0);
-        assert_eq!(header.num_u64, 1);
-        assert_eq!(header.num_i64, 1);
-        assert_eq!(header.num_f64, 0);
-        assert_eq!(header.num_string, 1);
-        assert_eq!(header.num_json, 0);
-        assert_eq!(header.num_bytes, 0);
+        let header = DocHeader::assert_roundtrip(&output);
+        let expected = DocHeader {
+            num_string: 1,
+            num_u64: 1,
+            num_i64: 1,
+            ..DocHeader::new(0)
+        };
+        assert_eq!(header, expected);
 
         let fields = header.read_document_fields(&output, true);
         assert_eq!(fields.len(), 3);
@@ -500,4 +1442,827 @@ mod tests {
         assert_eq!(fields[1].value_type, ValueType::U64);
         assert_eq!(fields[2].value_type, ValueType::I64);
     }
+
+    #[test]
+    fn test_header_roundtrip_all_types() {
+        let mut lookup = get_lookup();
+        lookup.insert("score".to_string(), 3);
+        lookup.insert("raw".to_string(), 4);
+        lookup.insert("extra".to_string(), 5);
+        lookup.insert("missing".to_string(), 6);
+        lookup.insert("flag".to_string(), 7);
+
+        let mut extra = serde_json::Map::new();
+        extra.insert("nested".to_string(), serde_json::Value::Bool(true));
+        let values = doc_values! {
+            "name" => vec![DocValue::from("bobby"), DocValue::from("bob")],
+            "age" => 15_u64,
+            "time" => 12312311241241_i64,
+            "score" => 1.5_f64,
+            "raw" => vec![1_u8, 2, 3],
+            "extra" => extra,
+            "missing" => DocValue::Null,
+            "flag" => true,
+        };
+
+        let mut output = Vec::new();
+        encode_document_to(&mut output, 42, &lookup, values.len(), &values, None);
+
+        let header = DocHeader::assert_roundtrip(&output);
+        let expected = DocHeader {
+            timestamp: 42,
+            num_string: 2,
+            num_u64: 1,
+            num_i64: 1,
+            num_f64: 1,
+            num_bytes: 1,
+            num_json: 1,
+            num_null: 1,
+            num_bool: 1,
+        };
+        assert_eq!(header, expected);
+
+        let mut fields = header.read_document_fields(&output, true);
+        assert_eq!(fields.len(), 9);
+        let flag = fields.pop().unwrap();
+        assert_eq!(flag.field_id, 7);
+        assert!(matches!(field_to_value(flag), Ok(DocValue::Bool(true))));
+        let null = fields.pop().unwrap();
+        assert_eq!(null.field_id, 6);
+        assert!(matches!(field_to_value(null), Ok(DocValue::Null)));
+    }
+
+    #[test]
+    fn test_digest_is_stable() {
+        let values = doc_values! {
+            "name" => "bobby",
+            "age" => 15_u64,
+        };
+
+        let mut output = Vec::new();
+        let digest = encode_document_to(
+            &mut output,
+            0,
+            &get_lookup(),
+            values.len(),
+            &values,
+            None,
+        );
+        let with_hasher = encode_document_with_hasher(
+            &mut output,
+            0,
+            &get_lookup(),
+            values.len(),
+            &values,
+            None,
+            cityhash_sys::CityHash64Hasher::default(),
+        );
+        assert_eq!(digest, with_hasher);
+        assert_eq!(digest, DocDigest(1870355057371032657));
+        assert_eq!(digest.to_string(), "19f4d603de74ac51");
+        assert_eq!(DocDigest::from_le_bytes(digest.to_le_bytes()), digest);
+        assert_eq!(u64::from(digest), 1870355057371032657);
+    }
+
+    #[test]
+    fn test_schema_hash_algorithm() {
+        let values = doc_values! {
+            "name" => "bobby",
+            "age" => 15_u64,
+        };
+        let schema = get_schema(3).with_hash_algorithm(HashAlgorithm::Xxh3);
+
+        // The schema's layout does not depend on which algorithms are enabled.
+        let archived = rkyv::to_bytes::<_, 256>(&schema).unwrap();
+        let archived = rkyv::check_archived_root::<BasicSchema>(&archived).unwrap();
+        let loaded: BasicSchema = archived.deserialize(&mut rkyv::Infallible).unwrap();
+        assert_eq!(loaded.hash_algorithm(), HashAlgorithm::Xxh3);
+
+        let mut buffer = Vec::new();
+        let result = encode_document_with_schema(&mut buffer, 0, &schema, &values);
+
+        #[cfg(feature = "xxhash")]
+        {
+            let city =
+                encode_document_with_schema(&mut Vec::new(), 0, &get_schema(3), &values)
+                    .unwrap();
+            assert_ne!(result.unwrap(), city);
+        }
+
+        #[cfg(not(feature = "xxhash"))]
+        {
+            assert!(matches!(
+                result,
+                Err(EncodeError::UnsupportedHashAlgorithm(HashAlgorithm::Xxh3))
+            ));
+            assert!(buffer.is_empty());
+        }
+    }
+
+    #[cfg(feature = "xxhash")]
+    #[test]
+    fn test_digest_algorithms_differ() {
+        let values = doc_values! {
+            "name" => "bobby",
+            "age" => 15_u64,
+        };
+
+        let encode = || {
+            encode_document_with_hasher(
+                &mut Vec::new(),
+                0,
+                &get_lookup(),
+                values.len(),
+                &values,
+                None,
+                xxhash_rust::xxh3::Xxh3::new(),
+            )
+        };
+
+        let city = encode_document_to(
+            &mut Vec::new(),
+            0,
+            &get_lookup(),
+            values.len(),
+            &values,
+            None,
+        );
+        let xxh3 = encode();
+        assert_ne!(city, xxh3);
+        assert_eq!(xxh3, encode());
+    }
+
+    #[test]
+    fn test_rewrite_timestamp() {
+        let values = doc_values! {
+            "name" => "bobby",
+            "age" => 15_u64,
+            "time" => 12312311241241_i64,
+        };
+
+        let mut original = Vec::new();
+        let digest = encode_document_to(
+            &mut original,
+            1,
+            &get_lookup(),
+            values.len(),
+            &values,
+            None,
+        );
+
+        let rewritten = rewrite_timestamp(&original, 5).expect("Rewrite timestamp");
+        assert_eq!(rewritten.len(), original.len());
+        assert_eq!(rewritten[DOC_HEADER_SIZE..], original[DOC_HEADER_SIZE..]);
+
+        let header = DocHeader::try_read_from(&rewritten).expect("Read header");
+        assert_eq!(header.timestamp, 5);
+        assert_eq!(header.read_document_fields(&rewritten, true).len(), 3);
+
+        let mut expected = Vec::new();
+        let expected_digest = encode_document_to(
+            &mut expected,
+            5,
+            &get_lookup(),
+            values.len(),
+            &values,
+            None,
+        );
+        assert_eq!(rewritten, expected);
+        assert_eq!(digest, expected_digest);
+
+        assert!(rewrite_timestamp(&original[..DOC_HEADER_SIZE - 1], 5).is_none());
+    }
+
+    fn get_schema(num_fields: usize) -> BasicSchema {
+        let mut fields = get_lookup();
+        let mut field_info = vec![
+            FieldInfo::new(ValueType::String, false),
+            FieldInfo::new(ValueType::U64, false),
+            FieldInfo::new(ValueType::I64, false),
+        ];
+        for field_id in field_info.len()..num_fields {
+            fields.insert(format!("extra-{field_id}"), field_id as FieldId);
+            field_info.push(FieldInfo::new(ValueType::U64, false));
+        }
+
+        BasicSchema::new(fields, field_info, None)
+    }
+
+    #[test]
+    fn test_encode_json_value() {
+        let mut lookup = get_lookup();
+        lookup.insert("score".to_string(), 3);
+        lookup.insert("extra".to_string(), 4);
+        lookup.insert("active".to_string(), 5);
+        let field_info = vec![
+            FieldInfo::new(ValueType::String, true),
+            FieldInfo::new(ValueType::U64, false),
+            FieldInfo::new(ValueType::I64, false),
+            FieldInfo::new(ValueType::F64, false),
+            FieldInfo::new(ValueType::Json, false),
+            FieldInfo::new(ValueType::Bool, false),
+        ];
+        let schema =
+            BasicSchema::new(lookup, field_info, None).with_preserve_field_order(true);
+
+        let raw = r#"{
+            "name": ["bobby", "bob"],
+            "age": 15,
+            "time": -12,
+            "score": 1.5,
+            "extra": {"nested": "value"},
+            "active": false,
+            "unknown": "dropped"
+        }"#;
+
+        let doc = ReferencingDoc::new(raw.to_string(), 0).unwrap();
+        let mut expected = Vec::new();
+        let expected_digest =
+            encode_document_with_schema(&mut expected, 3, &schema, doc.as_values())
+                .unwrap();
+
+        let value = serde_json::from_str::<Value>(raw).unwrap();
+        let mut output = Vec::new();
+        let digest = encode_json_value_to(&mut output, 3, &schema, &value).unwrap();
+        assert_eq!(output, expected);
+        assert_eq!(digest, expected_digest);
+
+        let mut empty = Vec::new();
+        encode_json_value_to(&mut empty, 3, &schema, &Value::from("not an object"))
+            .unwrap();
+        let header = DocHeader::assert_roundtrip(&empty);
+        assert_eq!(header, DocHeader::new(3));
+    }
+
+    #[test]
+    fn test_schema_evolution() {
+        let values = doc_values! {
+            "name" => "bobby",
+            "age" => 15_u64,
+            "time" => 12312311241241_i64,
+        };
+
+        let mut old = Vec::new();
+        encode_document_with_schema(&mut old, 0, &get_schema(3), &values).unwrap();
+
+        let evolved = get_schema(3)
+            .with_field_alias("username", 0)
+            .with_retired_field(2)
+            .with_preserve_field_order(true);
+        let header = DocHeader::try_read_from(&old).expect("Read header");
+        let fields = header.read_document_fields_with_schema(
+            &old,
+            true,
+            &get_schema(3).with_retired_field(2),
+        );
+        assert_eq!(
+            fields.iter().map(|f| f.field_id).collect::<Vec<_>>(),
+            [0, 1]
+        );
+
+        let aliased = doc_values! {
+            "username" => "bobby",
+            "age" => 15_u64,
+            "time" => 12312311241241_i64,
+        };
+        let mut expected = Vec::new();
+        let expected_digest = encode_document_with_schema(
+            &mut expected,
+            0,
+            &evolved,
+            &doc_values! { "name" => "bobby", "age" => 15_u64 },
+        )
+        .unwrap();
+        let mut output = Vec::new();
+        let digest =
+            encode_document_with_schema(&mut output, 0, &evolved, &aliased).unwrap();
+        assert_eq!(output, expected);
+        assert_eq!(digest, expected_digest);
+
+        let header = DocHeader::try_read_from(&output).expect("Read header");
+        let fields = header.read_document_fields_with_schema(&output, true, &evolved);
+        assert_eq!(
+            fields.iter().map(|f| f.field_id).collect::<Vec<_>>(),
+            [1, 0]
+        );
+    }
+
+    #[test]
+    fn test_field_id_width_selection() {
+        assert_eq!(get_schema(3).field_id_width(), FieldIdWidth::U8);
+        assert_eq!(get_schema(255).field_id_width(), FieldIdWidth::U8);
+        assert_eq!(get_schema(256).field_id_width(), FieldIdWidth::U16);
+    }
+
+    #[test]
+    fn test_explicit_field_id_width() {
+        let values = doc_values! {
+            "name" => "bobby",
+            "age" => 15_u64,
+        };
+
+        let schema = get_schema(3)
+            .with_field_id_width(FieldIdWidth::U16)
+            .unwrap();
+        assert_eq!(schema.field_id_width(), FieldIdWidth::U16);
+
+        let mut narrow = Vec::new();
+        encode_document_with_schema(&mut narrow, 0, &get_schema(3), &values).unwrap();
+        let mut wide = Vec::new();
+        encode_document_with_schema(&mut wide, 0, &schema, &values).unwrap();
+        assert_eq!(wide.len(), narrow.len() + 2);
+
+        let header = DocHeader::try_read_from(&wide).expect("Read header");
+        let fields = header
+            .try_read_document_fields_with_schema(&wide, true, &schema)
+            .expect("Read fields");
+        assert_eq!(
+            fields.iter().map(|f| f.field_id).collect::<Vec<_>>(),
+            [0, 1]
+        );
+        assert!(header
+            .try_read_document_fields_with_schema(&wide[..wide.len() - 1], true, &schema)
+            .is_none());
+
+        let err = get_schema(257)
+            .with_field_id_width(FieldIdWidth::U8)
+            .err()
+            .expect("Field ID 256 should not fit within a u8");
+        assert_eq!(err.field_id, 256);
+        assert_eq!(err.width, FieldIdWidth::U8);
+
+        let err = get_schema(3)
+            .with_field_alias("wide", 256)
+            .with_field_id_width(FieldIdWidth::U8)
+            .err()
+            .expect("Alias ID 256 should not fit within a u8");
+        assert_eq!(err.field_id, 256);
+    }
+
+    #[test]
+    fn test_field_id_width_roundtrip() {
+        let values = doc_values! {
+            "name" => "bobby",
+            "age" => 15_u64,
+            "time" => 12312311241241_i64,
+            "extra-255" => 3_u64,
+        };
+
+        let mut narrow = Vec::new();
+        let narrow_schema = get_schema(3);
+        encode_document_with_schema(&mut narrow, 0, &narrow_schema, &values).unwrap();
+        assert_eq!(narrow.len(), 52);
+
+        let mut wide = Vec::new();
+        let wide_schema = get_schema(256);
+        encode_document_with_schema(&mut wide, 0, &wide_schema, &values).unwrap();
+        assert_eq!(wide.len(), 55 + 10);
+
+        for (buffer, schema) in [(&narrow, &narrow_schema), (&wide, &wide_schema)] {
+            let header = DocHeader::try_read_from(buffer).expect("Read header");
+            let fields = header.read_document_fields_with_width(
+                buffer,
+                true,
+                schema.field_id_width(),
+            );
+            let ids = fields.iter().map(|f| f.field_id).collect::<Vec<_>>();
+            let values = fields
+                .into_iter()
+                .map(|f| format!("{:?}", field_to_value(f).unwrap()))
+                .collect::<Vec<_>>();
+
+            if schema.field_id_width() == FieldIdWidth::U8 {
+                assert_eq!(ids, [0, 1, 2]);
+                assert_eq!(
+                    values[..],
+                    ["String(\"bobby\")", "U64(15)", "I64(12312311241241)"]
+                );
+            } else {
+                assert_eq!(ids, [0, 1, 255, 2]);
+                assert_eq!(values[2], "U64(3)");
+            }
+        }
+    }
+
+    #[test]
+    fn test_field_id_too_large() {
+        let schema = get_schema(3).with_field_alias("wide", 256);
+        assert_eq!(schema.field_id_width().max_field_id(), 255);
+
+        let values = doc_values! {
+            "name" => "bobby",
+            "wide" => 3_u64,
+        };
+        let mut buffer = Vec::new();
+        let err = encode_document_with_schema(&mut buffer, 0, &schema, &values)
+            .expect_err("Field ID should not fit within a u8");
+        assert!(matches!(
+            err,
+            EncodeError::FieldIdTooLarge {
+                field_id: 256,
+                width: FieldIdWidth::U8,
+            }
+        ));
+        assert!(buffer.is_empty());
+
+        let value = DocValue::U64(3);
+        let err = encode_document_by_id_strict(&mut buffer, 0, &schema, [(300, &value)])
+            .expect_err("Field ID should not fit within a u8");
+        assert!(matches!(
+            err,
+            EncodeError::FieldIdTooLarge { field_id: 300, .. }
+        ));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_byte_order_roundtrip() {
+        let values = doc_values! {
+            "name" => "bobby",
+            "age" => 15_u64,
+            "time" => -12312311241241_i64,
+            "extra-3" => 1.5_f64,
+        };
+
+        let little_schema = get_schema(4);
+        let big_schema = get_schema(4).with_byte_order(ByteOrder::BigEndian);
+
+        let mut little = Vec::new();
+        encode_document_with_schema(&mut little, 7, &little_schema, &values).unwrap();
+        let mut big = Vec::new();
+        encode_document_with_schema(&mut big, 7, &big_schema, &values).unwrap();
+        assert_eq!(little.len(), big.len());
+        assert_ne!(little, big);
+
+        for (buffer, schema) in [(&little, &little_schema), (&big, &big_schema)] {
+            let order = schema.byte_order();
+            let header =
+                DocHeader::try_read_from_with_order(buffer, order).expect("Read header");
+            assert_eq!(header.timestamp, 7);
+
+            let values = header
+                .read_document_fields_with_schema(buffer, true, schema)
+                .into_iter()
+                .map(|f| format!("{:?}", field_to_value(f).unwrap()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                values[..],
+                [
+                    "String(\"bobby\")",
+                    "U64(15)",
+                    "I64(-12312311241241)",
+                    "F64(1.5)",
+                ],
+            );
+
+            let rewritten = rewrite_timestamp_with_order(buffer, 9, order)
+                .expect("Rewrite timestamp");
+            let header = DocHeader::try_read_from_with_order(&rewritten, order).unwrap();
+            assert_eq!(header.timestamp, 9);
+        }
+
+        // The recorded byte order must be used, otherwise the header is misread.
+        let header = DocHeader::try_read_from(&big).expect("Read header");
+        assert_eq!(header.timestamp, 7 << 56);
+        assert_eq!(header.num_string, 1 << 8);
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        let values = doc_values! {
+            "name" => "bobby",
+            "agee" => 15_u64,
+            "tiem" => 12312311241241_i64,
+        };
+
+        let mut lenient = Vec::new();
+        encode_document_to(&mut lenient, 0, &get_lookup(), values.len(), &values, None);
+        let header = DocHeader::try_read_from(&lenient).expect("Read header");
+        assert_eq!(header.num_fields(), 1);
+
+        let mut strict = Vec::new();
+        let err = encode_document_strict(
+            &mut strict,
+            0,
+            &get_lookup(),
+            values.len(),
+            &values,
+            None,
+        )
+        .expect_err("Unknown fields should be rejected");
+        assert_eq!(err.0, ["agee", "tiem"]);
+        assert!(strict.is_empty());
+
+        let values = doc_values! {
+            "name" => "bobby",
+            "age" => 15_u64,
+        };
+        let digest = encode_document_strict(
+            &mut strict,
+            0,
+            &get_lookup(),
+            values.len(),
+            &values,
+            None,
+        )
+        .expect("Known fields should be encoded");
+        let mut expected = Vec::new();
+        let expected_digest = encode_document_to(
+            &mut expected,
+            0,
+            &get_lookup(),
+            values.len(),
+            &values,
+            None,
+        );
+        assert_eq!(strict, expected);
+        assert_eq!(digest, expected_digest);
+    }
+
+    #[test]
+    fn test_multi_value_roundtrip() {
+        let values = doc_values! {
+            "name" => vec![DocValue::from("bobby"), DocValue::from("bob")],
+            "age" => 15_u64,
+        };
+
+        let mut output = Vec::new();
+        encode_document_to(&mut output, 0, &get_lookup(), values.len(), &values, None);
+
+        let header = DocHeader::try_read_from(&output).expect("Read header");
+        assert_eq!(header.num_string, 2);
+        assert_eq!(header.num_u64, 1);
+
+        let fields = header.read_document_fields(&output, true);
+        let ids = fields.iter().map(|f| f.field_id).collect::<Vec<_>>();
+        assert_eq!(ids, [0, 0, 1]);
+        assert_eq!(fields[0].value, b"bobby");
+        assert_eq!(fields[1].value, b"bob");
+        assert_eq!(fields[2].value, 15_u64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_encode_by_id_matches_by_name() {
+        let values = doc_values! {
+            "name" => vec![DocValue::from("bobby"), DocValue::from("bob")],
+            "age" => 15_u64,
+            "time" => 12312311241241_i64,
+        };
+
+        let mut by_name = Vec::new();
+        let name_digest = encode_document_to(
+            &mut by_name,
+            3,
+            &get_lookup(),
+            values.len(),
+            &values,
+            None,
+        );
+
+        let name = [DocValue::from("bobby"), DocValue::from("bob")];
+        let age = DocValue::from(15_u64);
+        let time = DocValue::from(12312311241241_i64);
+        let by_id_values = [(2, &time), (0, &name[0]), (1, &age), (0, &name[1])];
+
+        let mut by_id = Vec::new();
+        let id_digest = encode_document_by_id(&mut by_id, 3, by_id_values, None);
+
+        assert_eq!(by_id, by_name);
+        assert_eq!(id_digest, name_digest);
+    }
+
+    #[test]
+    fn test_preserve_field_order() {
+        let name = DocValue::from("bobby");
+        let age = DocValue::from(15_u64);
+        let time = DocValue::from(12312311241241_i64);
+        let values = [(2, &time), (0, &name), (1, &age)];
+
+        let grouped_schema = get_schema(3);
+        let ordered_schema = get_schema(3).with_preserve_field_order(true);
+
+        let mut grouped = Vec::new();
+        let grouped_digest =
+            encode_document_by_id_strict(&mut grouped, 0, &grouped_schema, values)
+                .unwrap();
+        let mut ordered = Vec::new();
+        let ordered_digest =
+            encode_document_by_id_strict(&mut ordered, 0, &ordered_schema, values)
+                .unwrap();
+        assert_eq!(ordered.len(), grouped.len() + 3 * size_of::<u16>());
+        assert_eq!(grouped_digest, ordered_digest);
+
+        let read_ids = |buffer: &[u8], schema: &BasicSchema| {
+            let header = DocHeader::try_read_from(buffer).expect("Read header");
+            header
+                .read_document_fields_with_schema(buffer, true, schema)
+                .iter()
+                .map(|f| f.field_id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(read_ids(&grouped, &grouped_schema), [0, 1, 2]);
+        assert_eq!(read_ids(&ordered, &ordered_schema), [2, 0, 1]);
+
+        // Fields given by name are recorded in the order they are iterated in.
+        let fields = [
+            ("time", DocField::from(12312311241241_i64)),
+            ("name", DocField::from("bobby")),
+            ("age", DocField::from(15_u64)),
+        ];
+        let mut ordered = Vec::new();
+        let pairs = fields.iter().map(|(name, field)| (name, field));
+        encode_document_with_schema(&mut ordered, 0, &ordered_schema, pairs).unwrap();
+        assert_eq!(read_ids(&ordered, &ordered_schema), [2, 0, 1]);
+
+        // A map iterates alphabetically, so that is the order recorded.
+        let values = doc_values! {
+            "time" => 12312311241241_i64,
+            "name" => "bobby",
+            "age" => 15_u64,
+        };
+        let mut ordered = Vec::new();
+        encode_document_with_schema(&mut ordered, 0, &ordered_schema, &values).unwrap();
+        assert_eq!(read_ids(&ordered, &ordered_schema), [1, 0, 2]);
+
+        // Each position is stored as a `u16`.
+        let value = serde_json::json!({
+            "name": vec![Value::Null; 40_000],
+            "age": vec![Value::Bool(true); 30_000],
+        });
+        let mut buffer = Vec::new();
+        let err = encode_json_value_to(&mut buffer, 0, &ordered_schema, &value)
+            .expect_err("Positions should not fit within a u16");
+        assert!(matches!(err, EncodeError::TooManyValues));
+        assert!(buffer.is_empty());
+        encode_json_value_to(&mut buffer, 0, &grouped_schema, &value).unwrap();
+    }
+
+    #[test]
+    fn test_document_digest() {
+        let mut lookup = get_lookup();
+        lookup.insert("extra".to_string(), 3);
+        lookup.insert("raw".to_string(), 4);
+        let values = doc_values! {
+            "name" => vec![DocValue::from("bobby"), DocValue::from("bob")],
+            "age" => 15_u64,
+            "time" => 12312311241241_i64,
+            "extra" => serde_json::json!({"nested": ["value", 1]}).as_object().unwrap().clone(),
+            "raw" => vec![DocValue::from(b"a".to_vec()), DocValue::from(b"b".to_vec())],
+            "unknown" => 1_u64,
+        };
+
+        for hash_key in [None, Some(0), Some(1), Some(3), Some(4)] {
+            let mut output = Vec::new();
+            let expected = encode_document_to(
+                &mut output,
+                0,
+                &lookup,
+                values.len(),
+                &values,
+                hash_key,
+            );
+            let digest = document_digest(&lookup, &values, hash_key);
+            assert_eq!(digest, expected);
+        }
+    }
+
+    #[test]
+    fn test_expired_documents() {
+        let schema = get_schema(3).with_ttl(10);
+        let values = doc_values! {
+            "name" => "bobby",
+        };
+
+        let docs = [0, 5, 20]
+            .into_iter()
+            .map(|ts| {
+                let mut buffer = Vec::new();
+                encode_document_with_schema(&mut buffer, ts, &schema, &values).unwrap();
+                buffer
+            })
+            .collect::<Vec<_>>();
+
+        let live_at = |now: u64, schema: &BasicSchema| {
+            skip_expired(docs.iter().map(Vec::as_slice), schema, now)
+                .map(|doc| {
+                    DocHeader::try_read_from(doc)
+                        .expect("Read header")
+                        .timestamp
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(live_at(9, &schema), [0, 5, 20]);
+        assert_eq!(live_at(12, &schema), [5, 20]);
+        assert_eq!(live_at(30, &schema), [] as [u64; 0]);
+        assert_eq!(live_at(u64::MAX, &get_schema(3)), [0, 5, 20]);
+
+        let big_schema = get_schema(3)
+            .with_ttl(10)
+            .with_byte_order(ByteOrder::BigEndian);
+        let mut big = Vec::new();
+        encode_document_with_schema(&mut big, 5, &big_schema, &values).unwrap();
+        let truncated = &big[..DOC_HEADER_SIZE - 1];
+        let live = skip_expired([big.as_slice(), truncated], &big_schema, 12).count();
+        assert_eq!(live, 2);
+        assert_eq!(skip_expired([big.as_slice()], &big_schema, 15).count(), 0);
+
+        let header = DocHeader::new(u64::MAX - 1);
+        assert!(!header.is_expired(schema.ttl(), u64::MAX - 1));
+    }
+
+    #[test]
+    fn test_multi_value_digest_is_order_independent() {
+        let first = doc_values! {
+            "name" => vec![DocValue::from("bobby"), DocValue::from("bob"), DocValue::from("b")],
+            "age" => 15_u64,
+        };
+        let second = doc_values! {
+            "name" => vec![DocValue::from("b"), DocValue::from("bobby"), DocValue::from("bob")],
+            "age" => 15_u64,
+        };
+        let different = doc_values! {
+            "name" => vec![DocValue::from("b"), DocValue::from("bobby"), DocValue::from("bobb")],
+            "age" => 15_u64,
+        };
+
+        let digest = |values: &BTreeMap<Cow<'static, str>, DocField<'static>>| {
+            let mut output = Vec::new();
+            let digest = encode_document_to(
+                &mut output,
+                0,
+                &get_lookup(),
+                values.len(),
+                values,
+                None,
+            );
+            assert_eq!(document_digest(&get_lookup(), values, None), digest);
+            (output, digest)
+        };
+
+        let (first_output, first_digest) = digest(&first);
+        let (second_output, second_digest) = digest(&second);
+        assert_ne!(first_output, second_output);
+        assert_eq!(first_digest, second_digest);
+        assert_ne!(digest(&different).1, first_digest);
+
+        let name = [DocValue::from("bobby"), DocValue::from("bob")];
+        let age = DocValue::from(15_u64);
+        let mut output = Vec::new();
+        let by_id = encode_document_by_id(
+            &mut output,
+            0,
+            [(0, &name[1]), (1, &age), (0, &name[0])],
+            None,
+        );
+        let values = doc_values! {
+            "name" => vec![DocValue::from("bobby"), DocValue::from("bob")],
+            "age" => 15_u64,
+        };
+        assert_eq!(by_id, digest(&values).1);
+    }
+
+    #[test]
+    fn test_duplicate_single_fields() {
+        let mut fields = get_lookup();
+        fields.insert("tags".to_string(), 3);
+        let field_info = vec![
+            FieldInfo::new(ValueType::String, false),
+            FieldInfo::new(ValueType::U64, false),
+            FieldInfo::new(ValueType::I64, false),
+            FieldInfo::new(ValueType::String, true),
+        ];
+        let schema = BasicSchema::new(fields, field_info, None);
+
+        let name = [DocValue::from("bobby"), DocValue::from("bob")];
+        let age = DocValue::from(15_u64);
+        let tags = [DocValue::from("a"), DocValue::from("b")];
+
+        let mut buffer = Vec::new();
+        let values = [(0, &name[0]), (1, &age), (0, &name[1]), (1, &age)];
+        let err = encode_document_by_id_strict(&mut buffer, 0, &schema, values)
+            .expect_err("Duplicate single fields should be rejected");
+        assert!(
+            matches!(err, EncodeError::DuplicateFields(DuplicateFields(ids)) if ids == [0, 1])
+        );
+        assert!(buffer.is_empty());
+
+        let values = [(0, &name[0]), (3, &tags[0]), (1, &age), (3, &tags[1])];
+        let digest = encode_document_by_id_strict(&mut buffer, 0, &schema, values)
+            .expect("Multi fields should be encoded");
+
+        let mut expected = Vec::new();
+        let expected_digest = encode_document_with_schema(
+            &mut expected,
+            0,
+            &schema,
+            &doc_values! {
+                "name" => "bobby",
+                "age" => 15_u64,
+                "tags" => vec![DocValue::from("a"), DocValue::from("b")],
+            },
+        )
+        .unwrap();
+        assert_eq!(buffer, expected);
+        assert_eq!(digest, expected_digest);
+    }
 }
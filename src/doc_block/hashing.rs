@@ -0,0 +1,96 @@
+use std::fmt::{Display, Formatter};
+use std::hash::Hasher;
+
+use bytecheck::CheckBytes;
+use rkyv::{Archive, Deserialize, Serialize};
+
+#[repr(u8)]
+#[derive(
+    Debug, Default, Copy, Clone, Eq, PartialEq, Archive, Serialize, Deserialize,
+)]
+#[archive_attr(derive(CheckBytes))]
+/// The algorithm used to compute a document's digest.
+///
+/// This is recorded in the schema so readers can recompute digests. Every
+/// algorithm is always defined so schemas can be read by any build, even if
+/// the algorithm's feature is not enabled.
+pub enum HashAlgorithm {
+    #[default]
+    /// The 64 bit `CityHash` algorithm.
+    CityHash64 = 0,
+    /// The 64 bit `XXH3` algorithm.
+    ///
+    /// Encoding documents with this algorithm requires the `xxhash` feature.
+    Xxh3 = 1,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// The 8 byte digest of a document.
+pub struct DocDigest(pub u64);
+
+impl DocDigest {
+    /// Returns the digest as little endian bytes.
+    pub fn to_le_bytes(self) -> [u8; 8] {
+        self.0.to_le_bytes()
+    }
+
+    /// Creates a digest from its little endian bytes.
+    pub fn from_le_bytes(bytes: [u8; 8]) -> Self {
+        Self(u64::from_le_bytes(bytes))
+    }
+}
+
+impl Display for DocDigest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl From<u64> for DocDigest {
+    fn from(digest: u64) -> Self {
+        Self(digest)
+    }
+}
+
+impl From<DocDigest> for u64 {
+    fn from(digest: DocDigest) -> Self {
+        digest.0
+    }
+}
+
+/// A hasher which can be used to compute a document's digest.
+///
+/// The values of multi-value fields are each hashed with a default hasher
+/// before being combined into the document's digest.
+pub trait DocHasher: Default {
+    /// Adds the given bytes to the digest.
+    fn update(&mut self, buf: &[u8]);
+
+    /// Completes the digest.
+    fn finalize_u64(self) -> u64;
+}
+
+impl DocHasher for cityhash_sys::CityHash64Hasher {
+    #[inline]
+    fn update(&mut self, buf: &[u8]) {
+        self.write(buf);
+    }
+
+    #[inline]
+    fn finalize_u64(self) -> u64 {
+        self.finish()
+    }
+}
+
+#[cfg(feature = "xxhash")]
+impl DocHasher for xxhash_rust::xxh3::Xxh3 {
+    #[inline]
+    fn update(&mut self, buf: &[u8]) {
+        xxhash_rust::xxh3::Xxh3::update(self, buf);
+    }
+
+    #[inline]
+    fn finalize_u64(self) -> u64 {
+        self.digest()
+    }
+}
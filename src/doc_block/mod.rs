@@ -1,11 +1,30 @@
 mod encoding;
+mod hashing;
+mod patch;
 
 pub use encoding::{
+    document_digest,
+    encode_document_by_id,
+    encode_document_by_id_strict,
+    encode_document_strict,
     encode_document_to,
+    encode_document_with_hasher,
+    encode_document_with_schema,
+    encode_json_value_to,
     field_to_value,
+    rewrite_timestamp,
+    rewrite_timestamp_with_order,
+    skip_expired,
+    ByteOrder,
     Corrupted,
     DocHeader,
+    DuplicateFields,
+    EncodeError,
     Field,
     FieldId,
+    FieldIdWidth,
+    UnknownFields,
     ValueType,
 };
+pub use hashing::{DocDigest, DocHasher, HashAlgorithm};
+pub use patch::{encode_patch_to, encode_patch_with_schema, DocPatch, FieldPatch};
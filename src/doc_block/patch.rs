@@ -0,0 +1,358 @@
+use std::collections::BTreeMap;
+
+use crate::doc_block::{
+    encode_document_to,
+    encode_document_with_schema,
+    ByteOrder,
+    DocDigest,
+    DocHeader,
+    EncodeError,
+    Field,
+    FieldId,
+    FieldIdWidth,
+};
+use crate::document::DocField;
+use crate::schema::BasicSchema;
+
+/// A change to a single field of an existing document.
+pub enum FieldPatch<'a> {
+    /// Replaces all values of the field.
+    Set(DocField<'a>),
+    /// Removes the field from the document.
+    Remove,
+}
+
+/// Encodes a patch to an existing document into the provided buffer.
+///
+/// The patch references the target document by its digest and only carries
+/// the changed fields, with removed fields being recorded as tombstones.
+/// Any fields which are not within the lookup are ignored.
+///
+/// The patch is laid out as the target digest, the number of removed fields
+/// and their IDs followed by a regular document containing the set fields.
+pub fn encode_patch_to<'a: 'b, 'b, S: AsRef<str> + 'b>(
+    buffer: &mut Vec<u8>,
+    ts: u64,
+    target_digest: DocDigest,
+    fields_lookup: &BTreeMap<String, FieldId>,
+    changes: impl IntoIterator<Item = (&'b S, &'b FieldPatch<'a>)>,
+) {
+    let mut set = Vec::new();
+    let mut removed = Vec::new();
+    for (field_name, change) in changes {
+        match change {
+            FieldPatch::Set(field) => set.push((field_name, field)),
+            FieldPatch::Remove => {
+                if let Some(field_id) = fields_lookup.get(field_name.as_ref()) {
+                    removed.push(*field_id);
+                }
+            },
+        }
+    }
+
+    write_patch_header(
+        buffer,
+        target_digest,
+        &removed,
+        FieldIdWidth::default(),
+        ByteOrder::default(),
+    );
+    encode_document_to(buffer, ts, fields_lookup, set.len(), set, None);
+}
+
+/// Encodes a patch to an existing document into the provided buffer according to
+/// the given schema.
+///
+/// This behaves like [encode_patch_to] except field names are resolved with
+/// [BasicSchema::resolve_field], and the removed fields and set fields are
+/// written with the schema's field ID width and byte order, matching documents
+/// encoded with [encode_document_with_schema].
+///
+/// The buffer is left untouched if the set fields cannot be encoded.
+pub fn encode_patch_with_schema<'a: 'b, 'b, S: AsRef<str> + 'b>(
+    buffer: &mut Vec<u8>,
+    ts: u64,
+    target_digest: DocDigest,
+    schema: &BasicSchema,
+    changes: impl IntoIterator<Item = (&'b S, &'b FieldPatch<'a>)>,
+) -> Result<(), EncodeError> {
+    let mut set = Vec::new();
+    let mut removed = Vec::new();
+    for (field_name, change) in changes {
+        match change {
+            FieldPatch::Set(field) => set.push((field_name, field)),
+            FieldPatch::Remove => {
+                if let Some(field_id) = schema.resolve_field(field_name.as_ref()) {
+                    removed.push(field_id);
+                }
+            },
+        }
+    }
+
+    let start = buffer.len();
+    write_patch_header(
+        buffer,
+        target_digest,
+        &removed,
+        schema.field_id_width(),
+        schema.byte_order(),
+    );
+    if let Err(e) = encode_document_with_schema(buffer, ts, schema, set) {
+        buffer.truncate(start);
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+fn write_patch_header(
+    buffer: &mut Vec<u8>,
+    target_digest: DocDigest,
+    removed: &[FieldId],
+    width: FieldIdWidth,
+    order: ByteOrder,
+) {
+    buffer.extend_from_slice(&target_digest.to_le_bytes());
+    order.write_u16(buffer, removed.len() as u16);
+    for field_id in removed {
+        width.write_to(buffer, *field_id, order);
+    }
+}
+
+/// A decoded document patch.
+pub struct DocPatch<'a> {
+    /// The digest of the document the patch applies to.
+    pub target_digest: DocDigest,
+    /// The header of the patch's set fields.
+    pub header: DocHeader,
+    /// The IDs of the fields removed from the target document.
+    pub removed: Vec<FieldId>,
+    /// The fields set on the target document.
+    pub fields: Vec<Field<'a>>,
+}
+
+impl<'a> DocPatch<'a> {
+    /// Attempts to read a patch encoded with [encode_patch_to].
+    ///
+    /// Returns `None` if the patch is truncated or otherwise malformed.
+    pub fn try_read_from(mut buffer: &'a [u8]) -> Option<Self> {
+        let (target_digest, removed) = read_patch_header(
+            &mut buffer,
+            FieldIdWidth::default(),
+            ByteOrder::default(),
+        )?;
+
+        let header = DocHeader::try_read_from(buffer)?;
+        let fields = header.try_read_document_fields(buffer, true)?;
+
+        Some(Self {
+            target_digest,
+            header,
+            removed,
+            fields,
+        })
+    }
+
+    /// Attempts to read a patch encoded with [encode_patch_with_schema].
+    ///
+    /// Returns `None` if the patch is truncated or otherwise malformed.
+    pub fn try_read_from_with_schema(
+        mut buffer: &'a [u8],
+        schema: &BasicSchema,
+    ) -> Option<Self> {
+        let order = schema.byte_order();
+        let (target_digest, removed) =
+            read_patch_header(&mut buffer, schema.field_id_width(), order)?;
+
+        let header = DocHeader::try_read_from_with_order(buffer, order)?;
+        let fields =
+            header.try_read_document_fields_with_schema(buffer, true, schema)?;
+
+        Some(Self {
+            target_digest,
+            header,
+            removed,
+            fields,
+        })
+    }
+
+    /// Applies the patch over the fields of the target document.
+    ///
+    /// Fields which are set by the patch replace all existing values of that
+    /// field, removed fields are dropped and all other fields are kept as-is.
+    pub fn apply(self, base: Vec<Field<'a>>) -> Vec<Field<'a>> {
+        let mut fields = base
+            .into_iter()
+            .filter(|field| {
+                !self.removed.contains(&field.field_id)
+                    && !self.fields.iter().any(|f| f.field_id == field.field_id)
+            })
+            .collect::<Vec<_>>();
+
+        fields.extend(self.fields);
+
+        // Keep the fields in the same order as they would be read from a document.
+        fields.sort_by_key(|field| field.value_type);
+        fields
+    }
+}
+
+fn read_patch_header(
+    buffer: &mut &[u8],
+    width: FieldIdWidth,
+    order: ByteOrder,
+) -> Option<(DocDigest, Vec<FieldId>)> {
+    let target_digest = DocDigest::from_le_bytes(take(buffer)?);
+    let num_removed = order.read_u16(buffer)?;
+
+    let mut removed = Vec::with_capacity(num_removed as usize);
+    for _ in 0..num_removed {
+        removed.push(width.read_from(buffer, order)?);
+    }
+
+    Some((target_digest, removed))
+}
+
+#[inline]
+fn take<const N: usize>(buffer: &mut &[u8]) -> Option<[u8; N]> {
+    if buffer.len() < N {
+        return None;
+    }
+
+    let (bytes, rest) = buffer.split_at(N);
+    *buffer = rest;
+    bytes.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::FieldInfo;
+    use crate::{doc_values, field_to_value, ValueType};
+
+    fn get_lookup() -> BTreeMap<String, FieldId> {
+        let mut fields = BTreeMap::new();
+        fields.insert("name".to_string(), 0);
+        fields.insert("age".to_string(), 1);
+        fields.insert("time".to_string(), 2);
+        fields
+    }
+
+    #[test]
+    fn test_apply_patch() {
+        let values = doc_values! {
+            "name" => "bobby",
+            "age" => 15_u64,
+            "time" => 12312311241241_i64,
+        };
+
+        let mut doc = Vec::new();
+        let digest =
+            encode_document_to(&mut doc, 0, &get_lookup(), values.len(), &values, None);
+
+        let mut changes = BTreeMap::new();
+        changes.insert("age", FieldPatch::Set(DocField::from(16_u64)));
+        changes.insert("time", FieldPatch::Remove);
+
+        let mut patch = Vec::new();
+        encode_patch_to(&mut patch, 1, digest, &get_lookup(), &changes);
+
+        let patch = DocPatch::try_read_from(&patch).expect("Read patch");
+        assert_eq!(patch.target_digest, digest);
+        assert_eq!(patch.header.timestamp, 1);
+        assert_eq!(patch.removed, [2]);
+        assert_eq!(patch.fields.len(), 1);
+
+        let header = DocHeader::try_read_from(&doc).expect("Read header");
+        let patched = patch.apply(header.read_document_fields(&doc, true));
+        let values = patched
+            .into_iter()
+            .map(|f| (f.field_id, format!("{:?}", field_to_value(f).unwrap())))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            [
+                (0, "String(\"bobby\")".to_string()),
+                (1, "U64(16)".to_string())
+            ],
+        );
+
+        assert!(DocPatch::try_read_from(&[0; 9]).is_none());
+    }
+
+    #[test]
+    fn test_truncated_patch() {
+        let mut changes = BTreeMap::new();
+        changes.insert("name", FieldPatch::Set(DocField::from("bobby")));
+        changes.insert("time", FieldPatch::Remove);
+
+        let mut patch = Vec::new();
+        encode_patch_to(&mut patch, 1, DocDigest(0), &get_lookup(), &changes);
+        assert!(DocPatch::try_read_from(&patch).is_some());
+
+        for len in 0..patch.len() {
+            assert!(
+                DocPatch::try_read_from(&patch[..len]).is_none(),
+                "Patch truncated to {len} bytes should be rejected",
+            );
+        }
+    }
+
+    #[test]
+    fn test_patch_with_schema() {
+        let field_info = vec![
+            FieldInfo::new(ValueType::String, false),
+            FieldInfo::new(ValueType::U64, false),
+            FieldInfo::new(ValueType::I64, false),
+        ];
+        let schema = BasicSchema::new(get_lookup(), field_info, None)
+            .with_byte_order(ByteOrder::BigEndian)
+            .with_field_alias("years", 1);
+        assert_eq!(schema.field_id_width(), FieldIdWidth::U8);
+
+        let values = doc_values! {
+            "name" => "bobby",
+            "age" => 15_u64,
+            "time" => 12312311241241_i64,
+        };
+        let mut doc = Vec::new();
+        let digest = encode_document_with_schema(&mut doc, 0, &schema, &values).unwrap();
+
+        let mut changes = BTreeMap::new();
+        changes.insert("years", FieldPatch::Set(DocField::from(16_u64)));
+        changes.insert("time", FieldPatch::Remove);
+
+        let mut patch = Vec::new();
+        encode_patch_with_schema(&mut patch, 1, digest, &schema, &changes).unwrap();
+
+        // The patch is not readable without the schema's layout.
+        assert!(DocPatch::try_read_from(&patch).is_none());
+        for len in 0..patch.len() {
+            assert!(
+                DocPatch::try_read_from_with_schema(&patch[..len], &schema).is_none()
+            );
+        }
+
+        let patch =
+            DocPatch::try_read_from_with_schema(&patch, &schema).expect("Read patch");
+        assert_eq!(patch.target_digest, digest);
+        assert_eq!(patch.header.timestamp, 1);
+        assert_eq!(patch.removed, [2]);
+
+        let header = DocHeader::try_read_from_with_order(&doc, ByteOrder::BigEndian)
+            .expect("Read header");
+        let patched =
+            patch.apply(header.read_document_fields_with_schema(&doc, true, &schema));
+        let values = patched
+            .into_iter()
+            .map(|f| (f.field_id, format!("{:?}", field_to_value(f).unwrap())))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            [
+                (0, "String(\"bobby\")".to_string()),
+                (1, "U64(16)".to_string())
+            ],
+        );
+    }
+}
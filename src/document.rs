@@ -6,7 +6,7 @@ use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
 use serde::de::{Error, MapAccess, SeqAccess, Visitor};
 use serde::{Deserialize, Deserializer};
 use serde_json::{Map, Value};
-use smallvec::SmallVec;
+use smallvec::{smallvec, SmallVec};
 
 use crate::doc_block::ValueType;
 
@@ -27,7 +27,7 @@ pub struct ReferencingDoc {
 impl ReferencingDoc {
     /// Creates a new document using reference data to the raw string.
     pub fn new(raw: String, ts: u64) -> Result<Self, serde_json::Error> {
-        let s_ref = unsafe { mem::transmute::<_, &'static str>(raw.as_str()) };
+        let s_ref = unsafe { mem::transmute::<&str, &'static str>(raw.as_str()) };
         let values = serde_json::from_str(s_ref)?;
         Ok(Self { raw, ts, values })
     }
@@ -70,6 +70,44 @@ impl<'a> DocField<'a> {
         matches!(self, Self::Many(_))
     }
 
+    #[inline]
+    /// The number of values contained within the field.
+    pub fn len(&self) -> usize {
+        match self {
+            DocField::Single(_) => 1,
+            DocField::Many(values) => values.len(),
+        }
+    }
+
+    #[inline]
+    /// Returns if the field contains no values.
+    ///
+    /// This can only be the case for an empty multi-value field.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    /// Returns an iterator over the values of the field.
+    ///
+    /// Single value fields yield exactly one value.
+    pub fn iter(&self) -> std::slice::Iter<'_, DocValue<'a>> {
+        match self {
+            DocField::Single(value) => std::slice::from_ref(value).iter(),
+            DocField::Many(values) => values.iter(),
+        }
+    }
+
+    /// Consumes the field returning all of its values.
+    ///
+    /// Single value fields are wrapped into a one-element vec.
+    pub fn into_values(self) -> SmallVec<[DocValue<'a>; STACK_LEN]> {
+        match self {
+            DocField::Single(value) => smallvec![value],
+            DocField::Many(values) => values,
+        }
+    }
+
     #[inline]
     /// Returns the value type equivalent of this value.
     ///
@@ -122,6 +160,10 @@ impl<'de> Deserialize<'de> for DocField<'de> {
                 formatter.write_str("a string, int or float")
             }
 
+            fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E> {
+                Ok(DocValue::Bool(v).into())
+            }
+
             fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> {
                 Ok(DocValue::I64(v).into())
             }
@@ -209,6 +251,8 @@ pub enum DocValue<'a> {
     I64(i64),
     /// A single `f64` value.
     F64(f64),
+    /// A single `bool` value.
+    Bool(bool),
     /// A single `string` value.
     String(Cow<'a, str>),
     /// A single `bytes` value.
@@ -225,6 +269,7 @@ impl<'a> DocValue<'a> {
             DocValue::U64(_) => ValueType::U64,
             DocValue::I64(_) => ValueType::I64,
             DocValue::F64(_) => ValueType::F64,
+            DocValue::Bool(_) => ValueType::Bool,
             DocValue::String(_) => ValueType::String,
             DocValue::Bytes(_) => ValueType::Bytes,
             DocValue::Json(_) => ValueType::Json,
@@ -253,6 +298,10 @@ impl<'a, 'de: 'a> Deserialize<'de> for DocValue<'a> {
                 formatter.write_str("a string, int or float")
             }
 
+            fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E> {
+                Ok(DocValue::Bool(v))
+            }
+
             fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> {
                 Ok(DocValue::I64(v))
             }
@@ -351,8 +400,51 @@ impl_from!(DocValue, F64, f64);
 impl_from!(DocValue, U64, u32);
 impl_from!(DocValue, I64, i32);
 impl_from!(DocValue, F64, f32);
+impl_from!(DocValue, Bool, bool);
 impl_from!(DocValue, String, &'a str);
 impl_from!(DocValue, String, String);
 impl_from!(DocValue, String, Cow<'a, str>);
 impl_from!(DocValue, Bytes, Vec<u8>);
 impl_from!(DocValue, Json, Map<String, Value>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_field_values() {
+        let field = DocField::from(12_u64);
+        assert_eq!(field.len(), 1);
+        assert!(!field.is_empty());
+        assert!(matches!(field.iter().next(), Some(DocValue::U64(12))));
+        assert_eq!(field.iter().count(), 1);
+
+        let values = field.into_values();
+        assert_eq!(values.len(), 1);
+        assert!(matches!(values[0], DocValue::U64(12)));
+    }
+
+    #[test]
+    fn test_multi_field_values() {
+        let field = DocField::from(vec![DocValue::from("a"), DocValue::from("b")]);
+        assert_eq!(field.len(), 2);
+        assert!(!field.is_empty());
+
+        let names = field
+            .iter()
+            .map(|v| match v {
+                DocValue::String(s) => s.to_string(),
+                other => panic!("Unexpected value: {other:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b"]);
+
+        let values = field.into_values();
+        assert_eq!(values.len(), 2);
+
+        let empty = DocField::Many(SmallVec::new());
+        assert_eq!(empty.len(), 0);
+        assert!(empty.is_empty());
+        assert!(empty.into_values().is_empty());
+    }
+}
@@ -2,18 +2,52 @@ mod directories;
 mod doc_block;
 mod document;
 pub mod metadata;
-mod schema;
+pub mod schema;
 
 pub static DELETES_FILE_PATH_BASE: &str = "segment-deletes.terms";
 
-pub use directories::{DirectoryMerger, DirectoryReader, DirectoryWriter};
+#[cfg(feature = "cache")]
+pub use directories::CachedDirectory;
+#[cfg(feature = "encryption")]
+pub use directories::EncryptionKey;
+pub use directories::{
+    verify_segment,
+    verify_segment_bytes,
+    DirectoryMerger,
+    DirectoryReader,
+    DirectoryWriter,
+    SegmentProblem,
+    SegmentReport,
+};
 pub use doc_block::{
+    document_digest,
+    encode_document_by_id,
+    encode_document_by_id_strict,
+    encode_document_strict,
     encode_document_to,
+    encode_document_with_hasher,
+    encode_document_with_schema,
+    encode_json_value_to,
+    encode_patch_to,
+    encode_patch_with_schema,
     field_to_value,
+    rewrite_timestamp,
+    rewrite_timestamp_with_order,
+    skip_expired,
+    ByteOrder,
     Corrupted,
+    DocDigest,
+    DocHasher,
     DocHeader,
+    DocPatch,
+    DuplicateFields,
+    EncodeError,
     Field,
     FieldId,
+    FieldIdWidth,
+    FieldPatch,
+    HashAlgorithm,
+    UnknownFields,
     ValueType,
 };
 pub use document::{DocField, DocValue, ReferencingDoc};
@@ -1,13 +1,48 @@
 use std::array::TryFromSliceError;
 use std::collections::BTreeMap;
-use std::io::{ErrorKind, Write};
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
 use std::ops::Range;
+use std::path::Path;
 use std::{io, mem};
 
 use bytecheck::CheckBytes;
-use rkyv::{Archive, Deserialize, Serialize};
+use rkyv::de::deserializers::SharedDeserializeMap;
+use rkyv::ser::serializers::{AlignedSerializer, AllocSerializer};
+use rkyv::ser::Serializer;
+use rkyv::validation::validators::DefaultValidator;
+use rkyv::{AlignedVec, Archive, Deserialize, Serialize};
 
-pub const METADATA_HEADER_SIZE: usize = mem::size_of::<u64>() * 2;
+/// The size of the footer at the end of each segment.
+///
+/// The footer holds the start and length of the metadata as big endian `u64`s,
+/// the big endian CRC32 checksum of the metadata bytes and the big endian
+/// [FOOTER_VERSION], ending with the [FOOTER_MAGIC].
+pub const METADATA_HEADER_SIZE: usize =
+    mem::size_of::<u64>() * 2 + mem::size_of::<u32>() * 2 + FOOTER_MAGIC.len();
+/// The magic bytes at the very end of every segment.
+pub const FOOTER_MAGIC: [u8; 4] = *b"JCKY";
+/// The current version of the segment footer layout.
+pub const FOOTER_VERSION: u32 = 1;
+/// The size of the footer of legacy segments.
+///
+/// Legacy segments were written before the footer held a magic, version or
+/// checksum. Their footer is the start and end of the metadata as big endian
+/// `u64`s, and their metadata only holds the files and hot cache without being
+/// prefixed by a [METADATA_VERSION]. A footer is only read as a legacy footer
+/// when it is not valid in the current format, see [get_metadata_offsets].
+pub const LEGACY_FOOTER_SIZE: usize = mem::size_of::<u64>() * 2;
+/// The current version of the segment metadata format.
+///
+/// The version is written before the metadata itself:
+///
+/// - `1` holds the files, hot cache and the nonces of encrypted files.
+/// - `2` adds the names of the tantivy indexes within the segment.
+///
+/// Any other version is rejected as its layout is unknown.
+pub const METADATA_VERSION: u32 = 2;
+/// The size of the nonce used to encrypt each file in bytes.
+pub const NONCE_SIZE: usize = 12;
 
 #[repr(C)]
 #[derive(Debug, Default, Serialize, Deserialize, Archive)]
@@ -15,6 +50,10 @@ pub const METADATA_HEADER_SIZE: usize = mem::size_of::<u64>() * 2;
 pub struct SegmentMetadata {
     files: BTreeMap<String, Range<u64>>,
     hot_cache: Vec<u8>,
+    /// The nonces of any files which are encrypted.
+    nonces: BTreeMap<String, [u8; NONCE_SIZE]>,
+    /// The names of the tantivy indexes contained within the segment.
+    indexes: Vec<String>,
 }
 
 impl SegmentMetadata {
@@ -34,42 +73,409 @@ impl SegmentMetadata {
         &self.files
     }
 
+    /// The files within the segment sorted by their offset.
+    pub fn files_by_offset(&self) -> Vec<(&str, Range<u64>)> {
+        let mut files = self
+            .files
+            .iter()
+            .map(|(file, range)| (file.as_str(), range.clone()))
+            .collect::<Vec<_>>();
+        files.sort_by_key(|(_, range)| (range.start, range.end));
+        files
+    }
+
+    /// The ranges of unused bytes before and between the files within the segment.
+    pub fn gaps(&self) -> Vec<Range<u64>> {
+        let mut gaps = Vec::new();
+        let mut cursor = 0;
+        for (_, range) in self.files_by_offset() {
+            if range.start > cursor {
+                gaps.push(cursor..range.start);
+            }
+            cursor = cursor.max(range.end);
+        }
+        gaps
+    }
+
+    /// Records an index as being contained within the segment.
+    pub fn add_index(&mut self, name: String) {
+        if !self.indexes.contains(&name) {
+            self.indexes.push(name);
+        }
+    }
+
+    /// The names of the tantivy indexes contained within the segment.
+    pub fn indexes(&self) -> &[String] {
+        &self.indexes
+    }
+
+    /// Marks the file as encrypted with the given nonce.
+    pub fn add_nonce(&mut self, file: String, nonce: [u8; NONCE_SIZE]) {
+        self.nonces.insert(file, nonce);
+    }
+
+    /// Gets the nonce of the file if it is encrypted.
+    pub fn get_nonce(&self, file: &str) -> Option<[u8; NONCE_SIZE]> {
+        self.nonces.get(file).copied()
+    }
+
+    /// Estimates the serialized size of the metadata in bytes.
+    fn estimated_size(&self) -> usize {
+        let files = self
+            .files
+            .keys()
+            .map(|file| file.len() + FILE_ENTRY_OVERHEAD)
+            .sum::<usize>();
+        let nonces = self
+            .nonces
+            .keys()
+            .map(|file| file.len() + NONCE_SIZE + FILE_ENTRY_OVERHEAD)
+            .sum::<usize>();
+
+        let indexes = self
+            .indexes
+            .iter()
+            .map(|name| name.len() + mem::size_of::<rkyv::string::ArchivedString>())
+            .sum::<usize>();
+
+        files
+            + nonces
+            + indexes
+            + self.hot_cache.len()
+            + mem::size_of::<ArchivedSegmentMetadata>()
+    }
+
+    /// Serializes the metadata prefixed with the [METADATA_VERSION].
+    ///
+    /// The output buffer is sized from an estimate of the metadata so large
+    /// segments avoid repeatedly growing it, any scratch space needed beyond
+    /// the fixed size falls back to the heap.
     pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
-        rkyv::to_bytes::<_, 4096>(self)
-            .map(|buf| buf.into_vec())
-            .map_err(|e| {
-                io::Error::new(
-                    ErrorKind::Other,
-                    format!("Could not serialize metadata: {e:?}"),
-                )
-            })
+        let mut serializer = AllocSerializer::<4096>::new(
+            AlignedSerializer::new(AlignedVec::with_capacity(self.estimated_size())),
+            Default::default(),
+            Default::default(),
+        );
+        serializer.serialize_value(self).map_err(|e| {
+            io::Error::other(format!("Could not serialize metadata: {e:?}"))
+        })?;
+        let metadata = serializer.into_serializer().into_inner();
+
+        let mut buf = Vec::with_capacity(VERSION_SIZE + metadata.len());
+        buf.extend_from_slice(&METADATA_VERSION.to_le_bytes());
+        buf.extend_from_slice(&metadata);
+        Ok(buf)
     }
 
+    /// Deserializes the metadata written by [Self::to_bytes].
     pub fn from_buffer(buf: &[u8]) -> io::Result<Self> {
-        rkyv::from_bytes(buf).map_err(|e| {
-            io::Error::new(
-                ErrorKind::Other,
-                format!("Could not deserialize metadata: {e:?}"),
-            )
-        })
+        if buf.len() < VERSION_SIZE {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Segment metadata is too small to contain its version",
+            ));
+        }
+
+        let (version, buf) = buf.split_at(VERSION_SIZE);
+        let version = u32::from_le_bytes(version.try_into().unwrap());
+        match version {
+            // Some version 1 metadata was written with the indexes before the
+            // version was bumped, so fall back to the current layout.
+            1 => deserialize::<SegmentMetadataV1>(buf)
+                .map(Self::from)
+                .or_else(|_| deserialize::<Self>(buf)),
+            METADATA_VERSION => deserialize::<Self>(buf),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Segment metadata version {version} is not supported, \
+                    the latest supported version is {METADATA_VERSION}"
+                ),
+            )),
+        }
+    }
+
+    /// Deserializes the unversioned metadata of a legacy segment.
+    ///
+    /// See [LEGACY_FOOTER_SIZE] for the layout of legacy segments.
+    pub fn from_legacy_buffer(buf: &[u8]) -> io::Result<Self> {
+        deserialize::<LegacySegmentMetadata>(buf).map(Self::from)
     }
 }
 
+/// The size of the version prefixing the metadata.
+const VERSION_SIZE: usize = mem::size_of::<u32>();
+/// The estimated number of bytes each map entry adds beyond its file name.
+const FILE_ENTRY_OVERHEAD: usize = 64;
+
+#[repr(C)]
+#[derive(Debug, Default, Serialize, Deserialize, Archive)]
+#[archive_attr(repr(C), derive(CheckBytes, Debug))]
+/// The metadata of legacy segments.
+struct LegacySegmentMetadata {
+    files: BTreeMap<String, Range<u64>>,
+    hot_cache: Vec<u8>,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Serialize, Deserialize, Archive)]
+#[archive_attr(repr(C), derive(CheckBytes, Debug))]
+/// The layout of version 1 of the segment metadata.
+struct SegmentMetadataV1 {
+    files: BTreeMap<String, Range<u64>>,
+    hot_cache: Vec<u8>,
+    nonces: BTreeMap<String, [u8; NONCE_SIZE]>,
+}
+
+impl From<SegmentMetadataV1> for SegmentMetadata {
+    fn from(metadata: SegmentMetadataV1) -> Self {
+        Self {
+            files: metadata.files,
+            hot_cache: metadata.hot_cache,
+            nonces: metadata.nonces,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<LegacySegmentMetadata> for SegmentMetadata {
+    fn from(metadata: LegacySegmentMetadata) -> Self {
+        Self {
+            files: metadata.files,
+            hot_cache: metadata.hot_cache,
+            ..Default::default()
+        }
+    }
+}
+
+fn deserialize<T>(buf: &[u8]) -> io::Result<T>
+where
+    T: Archive,
+    T::Archived:
+        for<'a> CheckBytes<DefaultValidator<'a>> + Deserialize<T, SharedDeserializeMap>,
+{
+    // The metadata can start at any offset within the segment, so we must
+    // copy it into an aligned buffer before validating it.
+    let mut aligned = AlignedVec::with_capacity(buf.len());
+    aligned.extend_from_slice(buf);
+
+    rkyv::from_bytes::<T>(&aligned)
+        .map_err(|e| io::Error::other(format!("Could not deserialize metadata: {e:?}")))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The footer at the end of a segment which locates its metadata.
+pub struct SegmentFooter {
+    /// The range of the metadata within the segment.
+    pub metadata: Range<u64>,
+    /// The checksum of the metadata bytes.
+    ///
+    /// This is `None` for legacy segments, see [LEGACY_FOOTER_SIZE].
+    pub checksum: Option<u32>,
+}
+
+impl SegmentFooter {
+    /// Returns if the footer belongs to a legacy segment.
+    pub fn is_legacy(&self) -> bool {
+        self.checksum.is_none()
+    }
+
+    /// The size of the footer in bytes.
+    pub fn size(&self) -> usize {
+        if self.is_legacy() {
+            LEGACY_FOOTER_SIZE
+        } else {
+            METADATA_HEADER_SIZE
+        }
+    }
+
+    /// The range of the metadata within the segment.
+    pub fn metadata_range(&self) -> Range<usize> {
+        self.metadata.start as usize..self.metadata.end as usize
+    }
+
+    /// Checks the metadata bytes match the footer's checksum.
+    ///
+    /// Legacy segments have no checksum so are always accepted.
+    pub fn verify_checksum(&self, metadata: &[u8]) -> io::Result<()> {
+        match self.checksum {
+            Some(checksum) if metadata_checksum(metadata) != checksum => {
+                Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "Segment metadata does not match its checksum",
+                ))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Deserializes the metadata bytes located by the footer.
+    ///
+    /// The metadata is not checked against the checksum, see [Self::verify_checksum].
+    pub fn decode_metadata(&self, metadata: &[u8]) -> io::Result<SegmentMetadata> {
+        if self.is_legacy() {
+            SegmentMetadata::from_legacy_buffer(metadata)
+        } else {
+            SegmentMetadata::from_buffer(metadata)
+        }
+    }
+}
+
+/// Reads the footer from the end of a segment with the given length.
+///
+/// `footer` must end at the end of the segment. The footer is only read in the
+/// current format if it ends with the [FOOTER_MAGIC], has the [FOOTER_VERSION]
+/// and its metadata ends exactly where the footer starts, otherwise it is read
+/// as a legacy footer whose metadata must end before the footer.
 pub fn get_metadata_offsets(
-    mut offset_slice: &[u8],
-) -> Result<(u64, u64), TryFromSliceError> {
-    let start = read_be_u64(&mut offset_slice)?;
-    let len = read_be_u64(&mut offset_slice)?;
-    Ok((start, len))
+    footer: &[u8],
+    segment_len: u64,
+) -> io::Result<SegmentFooter> {
+    let current = footer
+        .ends_with(&FOOTER_MAGIC)
+        .then(|| read_current_footer(footer))
+        .flatten();
+
+    if let Some((FOOTER_VERSION, footer)) = &current {
+        let footer_start = segment_len.checked_sub(METADATA_HEADER_SIZE as u64);
+        if footer_start == Some(footer.metadata.end) {
+            return Ok(footer.clone());
+        }
+    }
+
+    if let Some(footer) = read_legacy_footer(footer, segment_len) {
+        return Ok(footer);
+    }
+
+    Err(match current {
+        Some((version, _)) if version != FOOTER_VERSION => io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Segment footer version {version} is not supported, \
+                the latest supported version is {FOOTER_VERSION}"
+            ),
+        ),
+        Some(_) => io::Error::new(
+            ErrorKind::InvalidData,
+            "Segment metadata offsets are out of bounds",
+        ),
+        None if footer.len() < LEGACY_FOOTER_SIZE => too_small(),
+        None => invalid_legacy_footer(),
+    })
+}
+
+/// Reads the footer version and the footer in the current format.
+fn read_current_footer(footer: &[u8]) -> Option<(u32, SegmentFooter)> {
+    let mut footer = &footer[footer.len().checked_sub(METADATA_HEADER_SIZE)?..];
+    let start = read_be_u64(&mut footer).ok()?;
+    let len = read_be_u64(&mut footer).ok()?;
+    let checksum = read_be_u32(&mut footer).ok()?;
+    let version = read_be_u32(&mut footer).ok()?;
+
+    let footer = SegmentFooter {
+        metadata: start..start.checked_add(len)?,
+        checksum: Some(checksum),
+    };
+    Some((version, footer))
+}
+
+/// Reads a legacy footer, checking its metadata ends before the footer.
+fn read_legacy_footer(footer: &[u8], segment_len: u64) -> Option<SegmentFooter> {
+    let mut footer = &footer[footer.len().checked_sub(LEGACY_FOOTER_SIZE)?..];
+    let start = read_be_u64(&mut footer).ok()?;
+    let end = read_be_u64(&mut footer).ok()?;
+
+    let footer_start = segment_len.checked_sub(LEGACY_FOOTER_SIZE as u64)?;
+    (start <= end && end <= footer_start).then_some(SegmentFooter {
+        metadata: start..end,
+        checksum: None,
+    })
+}
+
+fn too_small() -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        "Segment is too small to contain the metadata offsets",
+    )
+}
+
+fn invalid_legacy_footer() -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        "Segment footer is missing its magic bytes and is not a valid legacy footer, \
+        the segment is either corrupt or uses an unsupported footer format",
+    )
+}
+
+/// Computes the checksum of the metadata bytes stored in the segment footer.
+pub fn metadata_checksum(metadata: &[u8]) -> u32 {
+    crc32fast::hash(metadata)
+}
+
+/// Locates the metadata within the complete bytes of an exported segment.
+///
+/// The returned range is checked to be within the segment and before the footer,
+/// and the metadata within it is checked against the footer's checksum.
+pub fn get_metadata_range(segment: &[u8]) -> io::Result<Range<usize>> {
+    let footer = locate_metadata(segment)?;
+    let range = footer.metadata_range();
+    footer.verify_checksum(&segment[range.clone()])?;
+    Ok(range)
+}
+
+/// Reads the footer of an exported segment, checking the metadata it locates
+/// is within the segment and before the footer.
+///
+/// Unlike [get_metadata_range] the metadata is not checked against the checksum.
+pub fn locate_metadata(segment: &[u8]) -> io::Result<SegmentFooter> {
+    get_metadata_offsets(segment, segment.len() as u64)
+}
+
+/// Reads the metadata of the segment file at the given path.
+///
+/// Only the footer and metadata are read from the file, the bytes of the files
+/// within the segment are never loaded.
+pub fn read_metadata(path: impl AsRef<Path>) -> io::Result<SegmentMetadata> {
+    let mut file = File::open(path)?;
+    let footer = read_footer(&mut file)?;
+
+    let mut buf = vec![0; (footer.metadata.end - footer.metadata.start) as usize];
+    file.seek(SeekFrom::Start(footer.metadata.start))?;
+    file.read_exact(&mut buf)?;
+
+    footer.verify_checksum(&buf)?;
+    footer.decode_metadata(&buf)
 }
 
+/// Reads the footer from the end of a segment.
+///
+/// Up to the last [METADATA_HEADER_SIZE] bytes of the reader are read, and the
+/// metadata range is checked to be before the footer. The reader is left
+/// positioned at its end.
+pub fn read_footer<R: Read + Seek>(reader: &mut R) -> io::Result<SegmentFooter> {
+    let segment_len = reader.seek(SeekFrom::End(0))?;
+    let footer_len = segment_len.min(METADATA_HEADER_SIZE as u64);
+
+    let mut footer = [0; METADATA_HEADER_SIZE];
+    let footer = &mut footer[..footer_len as usize];
+    reader.seek(SeekFrom::Start(segment_len - footer_len))?;
+    reader.read_exact(footer)?;
+
+    get_metadata_offsets(footer, segment_len)
+}
+
+/// Writes the segment footer containing the metadata offsets and checksum.
 pub fn write_metadata_offsets<W: Write>(
     file: &mut W,
     start: u64,
     len: u64,
+    checksum: u32,
 ) -> io::Result<()> {
     file.write_all(&start.to_be_bytes())?;
     file.write_all(&len.to_be_bytes())?;
+    file.write_all(&checksum.to_be_bytes())?;
+    file.write_all(&FOOTER_VERSION.to_be_bytes())?;
+    file.write_all(&FOOTER_MAGIC)?;
 
     Ok(())
 }
@@ -82,3 +488,308 @@ fn read_be_u64(input: &mut &[u8]) -> Result<u64, TryFromSliceError> {
 
     Ok(u64::from_be_bytes(converted))
 }
+
+fn read_be_u32(input: &mut &[u8]) -> Result<u32, TryFromSliceError> {
+    let (int_bytes, rest) = input.split_at(mem::size_of::<u32>());
+    *input = rest;
+
+    let converted = int_bytes.try_into()?;
+
+    Ok(u32::from_be_bytes(converted))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::path::PathBuf;
+
+    use tantivy::directory::OwnedBytes;
+    use tantivy::Directory;
+
+    use super::*;
+    use crate::directories::{exported_segment, populated_writer};
+    use crate::DirectoryReader;
+
+    fn versioned(version: u32, metadata: &[u8]) -> Vec<u8> {
+        let mut buf = version.to_le_bytes().to_vec();
+        buf.extend_from_slice(metadata);
+        buf
+    }
+
+    #[test]
+    fn test_current_version_roundtrip() {
+        let mut metadata = SegmentMetadata::default();
+        metadata.add_file("hello.txt".to_string(), 0..5);
+        metadata.add_nonce("hello.txt".to_string(), [1; NONCE_SIZE]);
+
+        let bytes = metadata.to_bytes().unwrap();
+        assert_eq!(bytes[..VERSION_SIZE], METADATA_VERSION.to_le_bytes());
+
+        let loaded = SegmentMetadata::from_buffer(&bytes).unwrap();
+        assert_eq!(loaded.files(), metadata.files());
+        assert_eq!(loaded.get_nonce("hello.txt"), Some([1; NONCE_SIZE]));
+    }
+
+    #[test]
+    fn test_read_metadata() {
+        let (_, segment) = exported_segment();
+
+        let out = tempfile::tempdir().unwrap();
+        let path = out.path().join("segment");
+        std::fs::write(&path, &segment).unwrap();
+
+        let metadata = read_metadata(&path).unwrap();
+        let reader =
+            DirectoryReader::from_segment_bytes("segment", OwnedBytes::new(segment))
+                .unwrap();
+        assert!(!metadata.files().is_empty());
+        assert_eq!(
+            metadata
+                .files()
+                .keys()
+                .map(PathBuf::from)
+                .collect::<BTreeSet<_>>(),
+            reader.files(),
+        );
+
+        std::fs::write(&path, b"short").unwrap();
+        let err = read_metadata(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_indexes() {
+        let writer = populated_writer();
+        writer.add_index("logs");
+        writer.add_index("metrics");
+        writer.add_index("logs");
+
+        let out = tempfile::tempdir().unwrap();
+        let path = out.path().join("segment");
+        writer.write_segment(File::create(&path).unwrap()).unwrap();
+
+        let metadata = read_metadata(&path).unwrap();
+        assert_eq!(metadata.indexes(), ["logs", "metrics"]);
+
+        let reader = DirectoryReader::from_segment_bytes(
+            "segment",
+            OwnedBytes::new(std::fs::read(&path).unwrap()),
+        )
+        .unwrap();
+        assert_eq!(reader.indexes(), ["logs", "metrics"]);
+    }
+
+    #[test]
+    fn test_metadata_checksum() {
+        let (_, mut segment) = exported_segment();
+
+        let range = get_metadata_range(&segment).unwrap();
+        let footer = get_metadata_offsets(&segment, segment.len() as u64).unwrap();
+        assert_eq!(
+            footer.checksum,
+            Some(metadata_checksum(&segment[range.clone()])),
+        );
+
+        segment[range.start + VERSION_SIZE] ^= u8::MAX;
+        let err = get_metadata_range(&segment).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let err =
+            DirectoryReader::from_segment_bytes("segment", OwnedBytes::new(segment))
+                .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_footer() {
+        let (_, segment) = exported_segment();
+
+        let footer = read_footer(&mut std::io::Cursor::new(&segment)).unwrap();
+        let range = get_metadata_range(&segment).unwrap();
+        assert_eq!(footer.metadata_range(), range);
+        assert_eq!(footer.checksum, Some(metadata_checksum(&segment[range])));
+        assert_eq!(footer, locate_metadata(&segment).unwrap());
+
+        let err = read_footer(&mut std::io::Cursor::new(b"short")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_footer_format() {
+        let metadata = SegmentMetadata::default().to_bytes().unwrap();
+        let mut segment = b"hello".to_vec();
+        segment.extend_from_slice(&metadata);
+        write_metadata_offsets(
+            &mut segment,
+            5,
+            metadata.len() as u64,
+            metadata_checksum(&metadata),
+        )
+        .unwrap();
+
+        assert!(segment.ends_with(&FOOTER_MAGIC));
+        assert_eq!(get_metadata_range(&segment).unwrap(), 5..5 + metadata.len());
+
+        // Without the magic the footer is read as a legacy footer.
+        let mut old = segment[..segment.len() - METADATA_HEADER_SIZE].to_vec();
+        old.extend_from_slice(&5_u64.to_be_bytes());
+        old.extend_from_slice(&(metadata.len() as u64).to_be_bytes());
+        old.extend_from_slice(&metadata_checksum(&metadata).to_be_bytes());
+        let err = get_metadata_range(&old).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("not a valid legacy footer"));
+
+        let version_start = segment.len() - FOOTER_MAGIC.len() - 4;
+        segment[version_start..version_start + 4]
+            .copy_from_slice(&(FOOTER_VERSION + 1).to_be_bytes());
+        let err = get_metadata_range(&segment).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err
+            .to_string()
+            .contains("footer version 2 is not supported"));
+
+        let err = get_metadata_range(&FOOTER_MAGIC).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_files_by_offset() {
+        let mut metadata = SegmentMetadata::default();
+        metadata.add_file("c.txt".to_string(), 0..4);
+        metadata.add_file("a.txt".to_string(), 10..20);
+        metadata.add_file("b.txt".to_string(), 4..8);
+        metadata.add_file("d.txt".to_string(), 20..25);
+
+        let files = metadata.files_by_offset();
+        assert_eq!(
+            files,
+            [
+                ("c.txt", 0..4),
+                ("b.txt", 4..8),
+                ("a.txt", 10..20),
+                ("d.txt", 20..25)
+            ],
+        );
+        assert_eq!(metadata.gaps(), vec![Range { start: 8, end: 10 }]);
+
+        metadata.add_file("e.txt".to_string(), 2..3);
+        metadata.add_file("f.txt".to_string(), 30..32);
+        assert_eq!(metadata.gaps(), [8..10, 25..30]);
+
+        let mut metadata = SegmentMetadata::default();
+        metadata.add_file("a.txt".to_string(), 6..8);
+        assert_eq!(metadata.gaps(), vec![Range { start: 0, end: 6 }]);
+    }
+
+    #[test]
+    fn test_large_metadata_roundtrip() {
+        let mut metadata = SegmentMetadata::default();
+        for i in 0..100_000_u64 {
+            let file = format!("{i:08}.idx");
+            metadata.add_file(file.clone(), i * 10..(i + 1) * 10);
+            if i % 2 == 0 {
+                metadata.add_nonce(file, [i as u8; NONCE_SIZE]);
+            }
+        }
+        metadata.with_hot_cache(vec![7; 1 << 20]);
+
+        let bytes = metadata.to_bytes().unwrap();
+        assert!(bytes.len() <= metadata.estimated_size() + VERSION_SIZE);
+
+        let loaded = SegmentMetadata::from_buffer(&bytes).unwrap();
+        assert_eq!(loaded.files(), metadata.files());
+        assert_eq!(loaded.nonces, metadata.nonces);
+        assert_eq!(loaded.hot_cache, metadata.hot_cache);
+    }
+
+    #[test]
+    fn test_read_legacy_segment() {
+        // Written by the original `DirectoryWriter` before the footer held a
+        // magic, version or checksum.
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data/legacy.segment");
+        let segment = std::fs::read(&path).unwrap();
+
+        let footer = read_footer(&mut File::open(&path).unwrap()).unwrap();
+        assert!(footer.is_legacy());
+        assert_eq!(footer.size(), LEGACY_FOOTER_SIZE);
+        assert_eq!(footer, locate_metadata(&segment).unwrap());
+
+        let metadata = read_metadata(&path).unwrap();
+        assert_eq!(metadata.get_location("hello.txt"), Some(0..5));
+        assert_eq!(metadata.get_location("nested/world.txt"), Some(5..11));
+        assert!(metadata.get_nonce("hello.txt").is_none());
+        assert!(metadata.indexes().is_empty());
+
+        let reader =
+            DirectoryReader::from_segment_bytes(&path, OwnedBytes::new(segment.clone()))
+                .unwrap();
+        assert_eq!(reader.atomic_read("hello.txt".as_ref()).unwrap(), b"hello");
+        assert_eq!(
+            reader.atomic_read("nested/world.txt".as_ref()).unwrap(),
+            b"world!",
+        );
+
+        let report = crate::verify_segment_bytes(&segment);
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert_eq!(report.num_files, 2);
+    }
+
+    #[test]
+    fn test_read_version_1() {
+        let mut v1 = SegmentMetadataV1::default();
+        v1.files.insert("hello.txt".to_string(), 0..5);
+        v1.nonces.insert("hello.txt".to_string(), [1; NONCE_SIZE]);
+        let buf = versioned(1, &rkyv::to_bytes::<_, 256>(&v1).unwrap());
+
+        let loaded = SegmentMetadata::from_buffer(&buf).unwrap();
+        assert_eq!(loaded.get_location("hello.txt"), Some(0..5));
+        assert_eq!(loaded.get_nonce("hello.txt"), Some([1; NONCE_SIZE]));
+        assert!(loaded.indexes().is_empty());
+
+        // Version 1 metadata written with the indexes.
+        let mut metadata = SegmentMetadata::default();
+        metadata.add_file("hello.txt".to_string(), 0..5);
+        metadata.add_index("logs".to_string());
+        let current = metadata.to_bytes().unwrap();
+        let buf = versioned(1, &current[VERSION_SIZE..]);
+
+        let loaded = SegmentMetadata::from_buffer(&buf).unwrap();
+        assert_eq!(loaded.get_location("hello.txt"), Some(0..5));
+        assert_eq!(loaded.indexes(), ["logs"]);
+    }
+
+    #[test]
+    fn test_legacy_footer_ending_with_magic() {
+        for end in [
+            u64::from(u32::from_be_bytes(FOOTER_MAGIC)),
+            // The bytes before the magic also spell the current footer version.
+            u64::from(FOOTER_VERSION) << 32
+                | u64::from(u32::from_be_bytes(FOOTER_MAGIC)),
+        ] {
+            let mut footer = vec![0; METADATA_HEADER_SIZE - LEGACY_FOOTER_SIZE];
+            footer.extend_from_slice(&(end - 100).to_be_bytes());
+            footer.extend_from_slice(&end.to_be_bytes());
+            assert!(footer.ends_with(&FOOTER_MAGIC));
+
+            let segment_len = end + LEGACY_FOOTER_SIZE as u64;
+            let footer = get_metadata_offsets(&footer, segment_len).unwrap();
+            assert!(footer.is_legacy());
+            assert_eq!(footer.metadata, end - 100..end);
+        }
+    }
+
+    #[test]
+    fn test_unsupported_versions() {
+        let current = SegmentMetadata::default().to_bytes().unwrap();
+
+        for version in [0, METADATA_VERSION + 1] {
+            let buf = versioned(version, &current[VERSION_SIZE..]);
+            let err = SegmentMetadata::from_buffer(&buf).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+
+        let err = SegmentMetadata::from_buffer(&[1, 0]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
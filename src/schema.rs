@@ -1,9 +1,16 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use bytecheck::CheckBytes;
 use rkyv::{Archive, Deserialize, Serialize};
 
-use crate::ValueType;
+use crate::{ByteOrder, FieldIdWidth, HashAlgorithm, ValueType};
+
+#[derive(Debug, thiserror::Error)]
+#[error("Field ID {field_id} cannot be encoded with a field ID width of {width:?}")]
+pub struct FieldIdTooLarge {
+    pub field_id: u16,
+    pub width: FieldIdWidth,
+}
 
 #[repr(C)]
 #[derive(Archive, Serialize, Deserialize)]
@@ -15,6 +22,20 @@ pub struct BasicSchema {
     field_info: Vec<FieldInfo>,
     /// The field ID to use as the digest hash key.
     hash_key: Option<u16>,
+    /// The algorithm used to compute document digests.
+    hash_algorithm: HashAlgorithm,
+    /// The number of bytes used to encode each field ID.
+    field_id_width: FieldIdWidth,
+    /// The byte order used to encode document values.
+    byte_order: ByteOrder,
+    /// Whether documents record the original order of their fields.
+    preserve_field_order: bool,
+    /// How long documents live for after their timestamp, if they expire.
+    ttl: Option<u64>,
+    /// Previous names of fields mapping to their field ID.
+    field_aliases: BTreeMap<String, u16>,
+    /// The IDs of fields which are no longer part of the schema.
+    retired: BTreeSet<u16>,
 }
 
 impl BasicSchema {
@@ -25,10 +46,92 @@ impl BasicSchema {
         hash_key: Option<u16>,
     ) -> Self {
         Self {
+            field_id_width: FieldIdWidth::for_num_fields(field_info.len()),
             fields,
             field_info,
             hash_key,
+            hash_algorithm: HashAlgorithm::default(),
+            byte_order: ByteOrder::default(),
+            preserve_field_order: false,
+            ttl: None,
+            field_aliases: BTreeMap::new(),
+            retired: BTreeSet::new(),
+        }
+    }
+
+    /// Set the algorithm used to compute document digests.
+    pub fn with_hash_algorithm(mut self, hash_algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = hash_algorithm;
+        self
+    }
+
+    /// Set the number of bytes used to encode each field ID.
+    ///
+    /// By default the smallest width which fits every field is picked, this
+    /// allows a wider width to be used so fields can be added later without
+    /// changing how existing documents are encoded.
+    ///
+    /// Returns an error if a field or alias has an ID which does not fit the width.
+    pub fn with_field_id_width(
+        mut self,
+        field_id_width: FieldIdWidth,
+    ) -> Result<Self, FieldIdTooLarge> {
+        let too_large = self
+            .fields
+            .values()
+            .chain(self.field_aliases.values())
+            .find(|field_id| **field_id > field_id_width.max_field_id());
+        if let Some(field_id) = too_large {
+            return Err(FieldIdTooLarge {
+                field_id: *field_id,
+                width: field_id_width,
+            });
         }
+
+        self.field_id_width = field_id_width;
+        Ok(self)
+    }
+
+    /// Set the byte order used to encode document values.
+    pub fn with_byte_order(mut self, byte_order: ByteOrder) -> Self {
+        self.byte_order = byte_order;
+        self
+    }
+
+    /// Set how long documents live for after their timestamp.
+    ///
+    /// The TTL uses the same units as the document timestamps.
+    pub fn with_ttl(mut self, ttl: u64) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Set whether documents record the original order of their fields.
+    ///
+    /// This costs two bytes per value but allows the fields to be read back
+    /// in their original order rather than grouped by type.
+    pub fn with_preserve_field_order(mut self, enabled: bool) -> Self {
+        self.preserve_field_order = enabled;
+        self
+    }
+
+    /// Add an alias which resolves to the given field ID.
+    ///
+    /// This allows a field to be renamed while documents using the
+    /// previous name can still be encoded.
+    pub fn with_field_alias(mut self, alias: impl Into<String>, field_id: u16) -> Self {
+        self.field_aliases.insert(alias.into(), field_id);
+        self
+    }
+
+    /// Retire the given field ID.
+    ///
+    /// Retired fields are no longer encoded and are skipped when reading
+    /// documents back, but the ID is never reused so existing segments
+    /// remain readable.
+    pub fn with_retired_field(mut self, field_id: u16) -> Self {
+        self.retired.insert(field_id);
+        self
     }
 
     #[inline]
@@ -37,17 +140,89 @@ impl BasicSchema {
         &self.fields
     }
 
+    #[inline]
+    /// Previous names of fields mapping to their field ID.
+    pub fn field_aliases(&self) -> &BTreeMap<String, u16> {
+        &self.field_aliases
+    }
+
+    #[inline]
+    /// Returns if the given field ID has been retired.
+    pub fn is_retired(&self, field_id: u16) -> bool {
+        self.retired.contains(&field_id)
+    }
+
+    /// Resolves a field name or alias to its field ID.
+    ///
+    /// Returns `None` if the field is unknown or has been retired.
+    pub fn resolve_field(&self, name: &str) -> Option<u16> {
+        self.fields
+            .get(name)
+            .or_else(|| self.field_aliases.get(name))
+            .copied()
+            .filter(|field_id| !self.is_retired(*field_id))
+    }
+
     #[inline]
     /// The field ID to use as the digest hash key.
     pub fn hash_key(&self) -> Option<u16> {
         self.hash_key
     }
 
+    #[inline]
+    /// The algorithm used to compute document digests.
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm
+    }
+
+    #[inline]
+    /// The number of bytes used to encode each field ID.
+    pub fn field_id_width(&self) -> FieldIdWidth {
+        self.field_id_width
+    }
+
+    #[inline]
+    /// The byte order used to encode document values.
+    pub fn byte_order(&self) -> ByteOrder {
+        self.byte_order
+    }
+
+    #[inline]
+    /// Whether documents record the original order of their fields.
+    pub fn preserves_field_order(&self) -> bool {
+        self.preserve_field_order
+    }
+
+    #[inline]
+    /// How long documents live for after their timestamp, if they expire.
+    pub fn ttl(&self) -> Option<u64> {
+        self.ttl
+    }
+
     #[inline]
     /// Get the specific field information.
     pub fn info(&self, field_id: u16) -> &FieldInfo {
         &self.field_info[field_id as usize]
     }
+
+    #[inline]
+    /// The information of every field, indexed by field ID.
+    pub fn field_infos(&self) -> &[FieldInfo] {
+        &self.field_info
+    }
+
+    /// The ID, value type and whether the field is multi-valued for every field.
+    pub fn value_types(&self) -> impl Iterator<Item = (u16, ValueType, bool)> + '_ {
+        self.field_info.iter().enumerate().map(|(field_id, info)| {
+            (field_id as u16, info.value_type(), info.is_multi())
+        })
+    }
+
+    #[inline]
+    /// The number of fields within the schema.
+    pub fn num_fields(&self) -> usize {
+        self.field_info.len()
+    }
 }
 
 #[repr(C)]
@@ -80,3 +255,86 @@ impl FieldInfo {
         self.is_multi
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_infos() {
+        let mut fields = BTreeMap::new();
+        fields.insert("name".to_string(), 0);
+        fields.insert("age".to_string(), 1);
+        fields.insert("tags".to_string(), 2);
+        let field_info = vec![
+            FieldInfo::new(ValueType::String, false),
+            FieldInfo::new(ValueType::U64, false),
+            FieldInfo::new(ValueType::String, true),
+        ];
+        let schema = BasicSchema::new(fields, field_info, None);
+        assert_eq!(schema.num_fields(), 3);
+
+        let mut described = schema
+            .fields()
+            .iter()
+            .map(|(name, field_id)| {
+                let info = &schema.field_infos()[*field_id as usize];
+                (name.as_str(), info.value_type(), info.is_multi())
+            })
+            .collect::<Vec<_>>();
+        described.sort_by_key(|(name, ..)| *name);
+        assert_eq!(
+            described,
+            [
+                ("age", ValueType::U64, false),
+                ("name", ValueType::String, false),
+                ("tags", ValueType::String, true),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_resolve_field() {
+        let mut fields = BTreeMap::new();
+        fields.insert("full_name".to_string(), 0);
+        fields.insert("age".to_string(), 1);
+        fields.insert("legacy".to_string(), 2);
+        let field_info = vec![
+            FieldInfo::new(ValueType::String, false),
+            FieldInfo::new(ValueType::U64, false),
+            FieldInfo::new(ValueType::String, false),
+        ];
+        let schema = BasicSchema::new(fields, field_info, None)
+            .with_field_alias("name", 0)
+            .with_retired_field(2);
+
+        assert_eq!(schema.resolve_field("full_name"), Some(0));
+        assert_eq!(schema.resolve_field("name"), Some(0));
+        assert_eq!(schema.resolve_field("age"), Some(1));
+        assert_eq!(schema.resolve_field("legacy"), None);
+        assert_eq!(schema.resolve_field("unknown"), None);
+        assert!(schema.is_retired(2));
+        assert!(!schema.is_retired(0));
+    }
+
+    #[test]
+    fn test_value_types() {
+        let field_info = vec![
+            FieldInfo::new(ValueType::String, false),
+            FieldInfo::new(ValueType::F64, true),
+            FieldInfo::new(ValueType::Json, false),
+            FieldInfo::new(ValueType::Bytes, true),
+        ];
+        let schema = BasicSchema::new(BTreeMap::new(), field_info, None);
+
+        assert_eq!(
+            schema.value_types().collect::<Vec<_>>(),
+            [
+                (0, ValueType::String, false),
+                (1, ValueType::F64, true),
+                (2, ValueType::Json, false),
+                (3, ValueType::Bytes, true),
+            ],
+        );
+    }
+}